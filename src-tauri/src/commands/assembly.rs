@@ -0,0 +1,537 @@
+//! Reads the `[BepInPlugin]` attributes of a .NET assembly.
+//!
+//! Only as much of the PE file and its ECMA-335 metadata is parsed as is needed to get from the
+//! CustomAttribute table to the constructor arguments of the attribute: the GUID, name and
+//! version every BepInEx plugin declares.
+
+use crate::commands::checksum::BoxError;
+use serde::Serialize;
+
+/// Index of the CLI header in the PE data directories.
+const CLI_HEADER_DIRECTORY: usize = 14;
+const METADATA_SIGNATURE: u32 = 0x424A_5342;
+
+// Metadata tables, by number.
+const MODULE: usize = 0x00;
+const TYPE_REF: usize = 0x01;
+const TYPE_DEF: usize = 0x02;
+const FIELD: usize = 0x04;
+const METHOD_DEF: usize = 0x06;
+const PARAM: usize = 0x08;
+const INTERFACE_IMPL: usize = 0x09;
+const MEMBER_REF: usize = 0x0A;
+const CUSTOM_ATTRIBUTE: usize = 0x0C;
+const DECL_SECURITY: usize = 0x0E;
+const STAND_ALONE_SIG: usize = 0x11;
+const EVENT: usize = 0x14;
+const PROPERTY: usize = 0x17;
+const MODULE_REF: usize = 0x1A;
+const TYPE_SPEC: usize = 0x1B;
+const ASSEMBLY: usize = 0x20;
+const ASSEMBLY_REF: usize = 0x23;
+const FILE: usize = 0x26;
+const EXPORTED_TYPE: usize = 0x27;
+const MANIFEST_RESOURCE: usize = 0x28;
+const GENERIC_PARAM: usize = 0x2A;
+const METHOD_SPEC: usize = 0x2B;
+const GENERIC_PARAM_CONSTRAINT: usize = 0x2C;
+
+// Tables a coded index can point to, in tag order.
+const RESOLUTION_SCOPE: &[usize] = &[MODULE, MODULE_REF, ASSEMBLY_REF, TYPE_REF];
+const TYPE_DEF_OR_REF: &[usize] = &[TYPE_DEF, TYPE_REF, TYPE_SPEC];
+const MEMBER_REF_PARENT: &[usize] = &[TYPE_DEF, TYPE_REF, MODULE_REF, METHOD_DEF, TYPE_SPEC];
+const HAS_CONSTANT: &[usize] = &[FIELD, PARAM, PROPERTY];
+const HAS_CUSTOM_ATTRIBUTE: &[usize] = &[
+    METHOD_DEF,
+    FIELD,
+    TYPE_REF,
+    TYPE_DEF,
+    PARAM,
+    INTERFACE_IMPL,
+    MEMBER_REF,
+    MODULE,
+    DECL_SECURITY,
+    PROPERTY,
+    EVENT,
+    STAND_ALONE_SIG,
+    MODULE_REF,
+    TYPE_SPEC,
+    ASSEMBLY,
+    ASSEMBLY_REF,
+    FILE,
+    EXPORTED_TYPE,
+    MANIFEST_RESOURCE,
+    GENERIC_PARAM,
+    GENERIC_PARAM_CONSTRAINT,
+    METHOD_SPEC,
+];
+/// The tags 0, 1 and 4 are unused.
+const CUSTOM_ATTRIBUTE_TYPE: &[usize] = &[METHOD_DEF, MEMBER_REF];
+const CUSTOM_ATTRIBUTE_TYPE_MEMBER_REF: u32 = 3;
+
+/// What a plugin declares about itself with `[BepInPlugin(guid, name, version)]`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginAttribute {
+    pub guid: String,
+    pub name: Option<String>,
+    pub version: Option<String>,
+}
+
+fn malformed() -> BoxError {
+    "Not a valid .NET assembly".into()
+}
+
+/// Bounds checked little endian reads.
+#[derive(Clone, Copy)]
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn slice(&self, at: usize, len: usize) -> Result<&'a [u8], BoxError> {
+        at.checked_add(len)
+            .and_then(|end| self.0.get(at..end))
+            .ok_or_else(malformed)
+    }
+
+    fn from(&self, at: usize) -> Result<Bytes<'a>, BoxError> {
+        Ok(Bytes(self.0.get(at..).ok_or_else(malformed)?))
+    }
+
+    fn u8(&self, at: usize) -> Result<u8, BoxError> {
+        Ok(self.slice(at, 1)?[0])
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, BoxError> {
+        Ok(u16::from_le_bytes(self.slice(at, 2)?.try_into()?))
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, BoxError> {
+        Ok(u32::from_le_bytes(self.slice(at, 4)?.try_into()?))
+    }
+
+    fn u64(&self, at: usize) -> Result<u64, BoxError> {
+        Ok(u64::from_le_bytes(self.slice(at, 8)?.try_into()?))
+    }
+
+    /// Reads an index that is `width` bytes wide.
+    fn index(&self, at: usize, width: usize) -> Result<u32, BoxError> {
+        match width {
+            2 => Ok(self.u16(at)? as u32),
+            _ => self.u32(at),
+        }
+    }
+
+    /// A length prefixed with the compressed unsigned integer encoding, and the offset after it.
+    fn compressed(&self, at: usize) -> Result<(usize, usize), BoxError> {
+        let first = self.u8(at)? as usize;
+        if first & 0x80 == 0 {
+            Ok((first, at + 1))
+        } else if first & 0xC0 == 0x80 {
+            Ok((((first & 0x3F) << 8) | self.u8(at + 1)? as usize, at + 2))
+        } else if first & 0xE0 == 0xC0 {
+            let rest = self.slice(at + 1, 3)?;
+            let len = ((first & 0x1F) << 24)
+                | ((rest[0] as usize) << 16)
+                | ((rest[1] as usize) << 8)
+                | rest[2] as usize;
+            Ok((len, at + 4))
+        } else {
+            Err(malformed())
+        }
+    }
+}
+
+/// The metadata of the assembly in `data`, found through the CLI header.
+fn metadata(data: Bytes) -> Result<Bytes, BoxError> {
+    if data.slice(0, 2)? != b"MZ" {
+        return Err(malformed());
+    }
+    let pe = data.u32(0x3C)? as usize;
+    if data.slice(pe, 4)? != b"PE\0\0" {
+        return Err(malformed());
+    }
+    let coff = pe + 4;
+    let sections = data.u16(coff + 2)? as usize;
+    let optional = coff + 20;
+    let optional_size = data.u16(coff + 16)? as usize;
+    let directories = match data.u16(optional)? {
+        0x10B => optional + 96,
+        0x20B => optional + 112,
+        _ => return Err(malformed()),
+    };
+    let directory_count = data.u32(directories - 4)? as usize;
+    if directory_count <= CLI_HEADER_DIRECTORY {
+        return Err("Not a .NET assembly".into());
+    }
+    let section_table = optional + optional_size;
+    let to_offset = |rva: u32| -> Result<usize, BoxError> {
+        for section in 0..sections {
+            let header = section_table + section * 40;
+            let size = data.u32(header + 8)?.max(data.u32(header + 16)?);
+            let start = data.u32(header + 12)?;
+            if rva >= start && rva - start < size {
+                return Ok((rva - start) as usize + data.u32(header + 20)? as usize);
+            }
+        }
+        Err(malformed())
+    };
+
+    let cli_rva = data.u32(directories + CLI_HEADER_DIRECTORY * 8)?;
+    if cli_rva == 0 {
+        return Err("Not a .NET assembly".into());
+    }
+    let cli = to_offset(cli_rva)?;
+    let metadata = to_offset(data.u32(cli + 8)?)?;
+    let size = data.u32(cli + 12)? as usize;
+    Ok(Bytes(data.slice(metadata, size)?))
+}
+
+/// The metadata streams needed to read custom attributes.
+struct Streams<'a> {
+    tables: Bytes<'a>,
+    strings: Bytes<'a>,
+    blobs: Bytes<'a>,
+}
+
+impl<'a> Streams<'a> {
+    fn parse(metadata: Bytes<'a>) -> Result<Streams<'a>, BoxError> {
+        if metadata.u32(0)? != METADATA_SIGNATURE {
+            return Err(malformed());
+        }
+        let version_len = metadata.u32(12)? as usize;
+        let mut at = 16 + version_len;
+        let count = metadata.u16(at + 2)?;
+        at += 4;
+        let (mut tables, mut strings, mut blobs) = (None, None, None);
+        for _ in 0..count {
+            let offset = metadata.u32(at)? as usize;
+            let size = metadata.u32(at + 4)? as usize;
+            let name_start = at + 8;
+            let name_len = metadata
+                .from(name_start)?
+                .0
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(malformed)?;
+            let stream = Some(Bytes(metadata.slice(offset, size)?));
+            match metadata.slice(name_start, name_len)? {
+                b"#~" => tables = stream,
+                b"#Strings" => strings = stream,
+                b"#Blob" => blobs = stream,
+                _ => {}
+            }
+            // names are padded to a multiple of four bytes, including the terminator
+            at = name_start + (name_len + 4) / 4 * 4;
+        }
+        Ok(Streams {
+            tables: tables.ok_or_else(malformed)?,
+            strings: strings.ok_or_else(malformed)?,
+            blobs: blobs.unwrap_or(Bytes(&[])),
+        })
+    }
+
+    fn string(&self, index: u32) -> Result<&'a str, BoxError> {
+        let bytes = self.strings.from(index as usize)?.0;
+        let end = bytes.iter().position(|&b| b == 0).ok_or_else(malformed)?;
+        Ok(std::str::from_utf8(&bytes[..end])?)
+    }
+
+    fn blob(&self, index: u32) -> Result<Bytes<'a>, BoxError> {
+        let (len, start) = self.blobs.compressed(index as usize)?;
+        Ok(Bytes(self.blobs.slice(start, len)?))
+    }
+}
+
+/// Where the rows of the tables up to CustomAttribute are, and how wide their columns are.
+struct Tables<'a> {
+    data: Bytes<'a>,
+    rows: [u32; 64],
+    offsets: [usize; CUSTOM_ATTRIBUTE + 1],
+    row_sizes: [usize; CUSTOM_ATTRIBUTE + 1],
+    string_width: usize,
+    blob_width: usize,
+}
+
+impl<'a> Tables<'a> {
+    fn parse(data: Bytes<'a>) -> Result<Tables<'a>, BoxError> {
+        let heap_sizes = data.u8(6)?;
+        let valid = data.u64(8)?;
+        let mut rows = [0; 64];
+        let mut at = 24;
+        for (table, count) in rows.iter_mut().enumerate() {
+            if valid & (1 << table) != 0 {
+                *count = data.u32(at)?;
+                at += 4;
+            }
+        }
+        let mut tables = Tables {
+            data,
+            rows,
+            offsets: [0; CUSTOM_ATTRIBUTE + 1],
+            row_sizes: [0; CUSTOM_ATTRIBUTE + 1],
+            string_width: if heap_sizes & 0x01 != 0 { 4 } else { 2 },
+            blob_width: if heap_sizes & 0x04 != 0 { 4 } else { 2 },
+        };
+        let guid = if heap_sizes & 0x02 != 0 { 4 } else { 2 };
+        let (string, blob) = (tables.string_width, tables.blob_width);
+        let index = |table| tables.index_width(table);
+        let coded = |targets| tables.coded_width(targets);
+        let row_sizes = [
+            2 + string + 3 * guid,
+            coded(RESOLUTION_SCOPE) + 2 * string,
+            4 + 2 * string + coded(TYPE_DEF_OR_REF) + index(FIELD) + index(METHOD_DEF),
+            index(FIELD),
+            2 + string + blob,
+            index(METHOD_DEF),
+            8 + string + blob + index(PARAM),
+            index(PARAM),
+            4 + string,
+            index(TYPE_DEF) + coded(TYPE_DEF_OR_REF),
+            coded(MEMBER_REF_PARENT) + string + blob,
+            2 + coded(HAS_CONSTANT) + blob,
+            coded(HAS_CUSTOM_ATTRIBUTE) + coded(CUSTOM_ATTRIBUTE_TYPE) + blob,
+        ];
+        for (table, size) in row_sizes.into_iter().enumerate() {
+            tables.offsets[table] = at;
+            tables.row_sizes[table] = size;
+            at += size * tables.rows[table] as usize;
+        }
+        Ok(tables)
+    }
+
+    fn index_width(&self, table: usize) -> usize {
+        if self.rows[table] < 1 << 16 {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// Width of a coded index, whose low bits tell which of `targets` it points into.
+    fn coded_width(&self, targets: &[usize]) -> usize {
+        let tag_bits = usize::BITS - (targets.len() - 1).leading_zeros();
+        // CustomAttributeType reserves more tags than it uses
+        let tag_bits = if targets == CUSTOM_ATTRIBUTE_TYPE {
+            3
+        } else {
+            tag_bits
+        };
+        let largest = targets.iter().map(|&t| self.rows[t]).max().unwrap_or(0);
+        if largest < 1 << (16 - tag_bits) {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// The data of the one-based `row` of `table`.
+    fn row(&self, table: usize, row: u32) -> Result<Bytes<'a>, BoxError> {
+        if row == 0 || row > self.rows[table] {
+            return Err(malformed());
+        }
+        let at = self.offsets[table] + (row as usize - 1) * self.row_sizes[table];
+        Ok(Bytes(self.data.slice(at, self.row_sizes[table])?))
+    }
+}
+
+/// A SerString from a custom attribute blob, which may be null, and the offset after it.
+fn ser_string(blob: Bytes, at: usize) -> Result<(Option<String>, usize), BoxError> {
+    if blob.u8(at)? == 0xFF {
+        return Ok((None, at + 1));
+    }
+    let (len, start) = blob.compressed(at)?;
+    let text = std::str::from_utf8(blob.slice(start, len)?)?;
+    Ok((Some(text.to_string()), start + len))
+}
+
+/// Every `[BepInPlugin]` attribute in the assembly `data`. Assemblies that are not plugins, like
+/// libraries shipped with one, have none.
+pub fn read_plugin_attributes(data: &[u8]) -> Result<Vec<PluginAttribute>, BoxError> {
+    let streams = Streams::parse(metadata(Bytes(data))?)?;
+    let tables = Tables::parse(streams.tables)?;
+    let parent_width = tables.coded_width(HAS_CUSTOM_ATTRIBUTE);
+    let type_width = tables.coded_width(CUSTOM_ATTRIBUTE_TYPE);
+    let member_parent_width = tables.coded_width(MEMBER_REF_PARENT);
+    let scope_width = tables.coded_width(RESOLUTION_SCOPE);
+
+    let mut plugins = Vec::new();
+    for row in 1..=tables.rows[CUSTOM_ATTRIBUTE] {
+        let attribute = tables.row(CUSTOM_ATTRIBUTE, row)?;
+        let constructor = attribute.index(parent_width, type_width)?;
+        // BepInPlugin comes from BepInEx, so its constructor is always referenced
+        if constructor & 0x7 != CUSTOM_ATTRIBUTE_TYPE_MEMBER_REF {
+            continue;
+        }
+        let member = tables.row(MEMBER_REF, constructor >> 3)?;
+        let class = member.index(0, member_parent_width)?;
+        // tag 1 is TypeRef
+        if class & 0x7 != 1 {
+            continue;
+        }
+        let type_ref = tables.row(TYPE_REF, class >> 3)?;
+        let name = streams.string(type_ref.index(scope_width, tables.string_width)?)?;
+        let namespace = streams
+            .string(type_ref.index(scope_width + tables.string_width, tables.string_width)?)?;
+        if name != "BepInPlugin" || namespace != "BepInEx" {
+            continue;
+        }
+
+        let value = streams.blob(attribute.index(parent_width + type_width, tables.blob_width)?)?;
+        if value.u16(0)? != 0x0001 {
+            return Err(malformed());
+        }
+        let (guid, at) = ser_string(value, 2)?;
+        let (name, at) = ser_string(value, at)?;
+        let (version, _) = ser_string(value, at)?;
+        if let Some(guid) = guid {
+            plugins.push(PluginAttribute {
+                guid,
+                name,
+                version,
+            });
+        }
+    }
+    Ok(plugins)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn ser_string(bytes: &mut Vec<u8>, text: &str) {
+        bytes.push(text.len() as u8);
+        bytes.extend_from_slice(text.as_bytes());
+    }
+
+    /// A PE32 file with just enough metadata for one `[BepInPlugin]` attribute.
+    pub fn plugin_dll(guid: &str, name: &str, version: &str) -> Vec<u8> {
+        let mut strings = vec![0];
+        let mut string = |text: &str| {
+            let index = strings.len() as u16;
+            strings.extend_from_slice(text.as_bytes());
+            strings.push(0);
+            index
+        };
+        let (module_name, plugin, bepinex, ctor) = (
+            string("Plugin.dll"),
+            string("BepInPlugin"),
+            string("BepInEx"),
+            string(".ctor"),
+        );
+        let mut blobs = vec![0, 3, 0x20, 3, 0x01];
+        let signature = 1u16;
+        let value = blobs.len() as u16;
+        let mut attribute = vec![0x01, 0x00];
+        ser_string(&mut attribute, guid);
+        ser_string(&mut attribute, name);
+        ser_string(&mut attribute, version);
+        attribute.extend_from_slice(&[0, 0]);
+        blobs.push(attribute.len() as u8);
+        blobs.extend_from_slice(&attribute);
+
+        let mut tables = vec![0, 0, 0, 0, 2, 0, 0, 1];
+        let valid: u64 = 1 << MODULE | 1 << TYPE_REF | 1 << MEMBER_REF | 1 << CUSTOM_ATTRIBUTE;
+        tables.extend_from_slice(&valid.to_le_bytes());
+        tables.extend_from_slice(&0u64.to_le_bytes());
+        for _ in 0..4 {
+            tables.extend_from_slice(&1u32.to_le_bytes());
+        }
+        let mut row = |values: &[u16]| {
+            for value in values {
+                tables.extend_from_slice(&value.to_le_bytes());
+            }
+        };
+        row(&[0, module_name, 0, 0, 0]);
+        // resolution scope AssemblyRef 1, which the file does not need to contain
+        row(&[1 << 2 | 2, plugin, bepinex]);
+        // class TypeRef 1
+        row(&[1 << 3 | 1, ctor, signature]);
+        // parent Assembly 1, type MemberRef 1
+        row(&[1 << 5 | 14, 1 << 3 | 3, value]);
+        while tables.len() % 4 != 0 {
+            tables.push(0);
+        }
+        while strings.len() % 4 != 0 {
+            strings.push(0);
+        }
+        while blobs.len() % 4 != 0 {
+            blobs.push(0);
+        }
+
+        let version = b"v4.0.30319\0\0";
+        let headers_len = 16 + version.len() + 4 + (8 + 4) + (8 + 12) + (8 + 8);
+        let mut metadata = Vec::new();
+        metadata.extend_from_slice(&METADATA_SIGNATURE.to_le_bytes());
+        metadata.extend_from_slice(&[1, 0, 1, 0, 0, 0, 0, 0]);
+        metadata.extend_from_slice(&(version.len() as u32).to_le_bytes());
+        metadata.extend_from_slice(version);
+        metadata.extend_from_slice(&[0, 0, 3, 0]);
+        let mut offset = headers_len;
+        for (stream, name) in [
+            (&tables, &b"#~\0\0"[..]),
+            (&strings, &b"#Strings\0\0\0\0"[..]),
+            (&blobs, &b"#Blob\0\0\0"[..]),
+        ] {
+            metadata.extend_from_slice(&(offset as u32).to_le_bytes());
+            metadata.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+            metadata.extend_from_slice(name);
+            offset += stream.len();
+        }
+        assert_eq!(metadata.len(), headers_len);
+        metadata.extend_from_slice(&tables);
+        metadata.extend_from_slice(&strings);
+        metadata.extend_from_slice(&blobs);
+
+        let section_rva = 0x2000u32;
+        let mut section = vec![0; 72];
+        section[0..4].copy_from_slice(&72u32.to_le_bytes());
+        section[8..12].copy_from_slice(&(section_rva + 72).to_le_bytes());
+        section[12..16].copy_from_slice(&(metadata.len() as u32).to_le_bytes());
+        section.extend_from_slice(&metadata);
+
+        let mut pe = vec![0; 0x80];
+        pe[0..2].copy_from_slice(b"MZ");
+        pe[0x3C..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        pe.extend_from_slice(b"PE\0\0");
+        let mut coff = vec![0; 20];
+        coff[0..2].copy_from_slice(&0x14Cu16.to_le_bytes());
+        coff[2..4].copy_from_slice(&1u16.to_le_bytes());
+        coff[16..18].copy_from_slice(&224u16.to_le_bytes());
+        pe.extend_from_slice(&coff);
+        let mut optional = vec![0; 224];
+        optional[0..2].copy_from_slice(&0x10Bu16.to_le_bytes());
+        optional[92..96].copy_from_slice(&16u32.to_le_bytes());
+        let cli_directory = 96 + CLI_HEADER_DIRECTORY * 8;
+        optional[cli_directory..cli_directory + 4].copy_from_slice(&section_rva.to_le_bytes());
+        optional[cli_directory + 4..cli_directory + 8].copy_from_slice(&72u32.to_le_bytes());
+        pe.extend_from_slice(&optional);
+        let raw_offset = 0x200u32;
+        let mut header = vec![0; 40];
+        header[0..6].copy_from_slice(b".text\0");
+        header[8..12].copy_from_slice(&(section.len() as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&section_rva.to_le_bytes());
+        header[16..20].copy_from_slice(&(section.len() as u32).to_le_bytes());
+        header[20..24].copy_from_slice(&raw_offset.to_le_bytes());
+        pe.extend_from_slice(&header);
+        pe.resize(raw_offset as usize, 0);
+        pe.extend_from_slice(&section);
+        pe
+    }
+
+    #[test]
+    fn test_read_plugin_attributes() {
+        let dll = plugin_dll("carra.lethe", "Lethe", "1.2.0");
+        assert_eq!(
+            read_plugin_attributes(&dll).unwrap(),
+            vec![PluginAttribute {
+                guid: "carra.lethe".to_string(),
+                name: Some("Lethe".to_string()),
+                version: Some("1.2.0".to_string()),
+            }]
+        );
+
+        assert!(read_plugin_attributes(b"not a dll").is_err());
+        // cut off in the middle of the metadata
+        assert!(read_plugin_attributes(&dll[..dll.len() - 40]).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::commands::cancel::CancelToken;
+use crate::commands::checksum::{remove_dir_if_exists, remove_file_if_exists, BoxError};
+use crate::commands::extract::{extract_zip, MAX_EXTRACTED_SIZE};
+use crate::commands::install_manifest::{remove_empty_parents, InstallComponent, InstallManifest};
+use crate::commands::progress::ProgressReporter;
+use crate::commands::release::{verify_file, BepInExRelease};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Folders BepInEx fills from the game's assemblies on the first launch. Their content depends on
+/// the BepInEx version, so they are cleared whenever it changes and regenerated by the new one.
+const GENERATED_DIRS: &[&str] = &[
+    bepinex_path!("interop"),
+    bepinex_path!("unity-libs"),
+    bepinex_path!("cache"),
+];
+
+/// The BepInEx install recorded in `game_dir`, if any. Installs made before versions were
+/// recorded have none.
+pub fn installed_bepinex(game_dir: &Path) -> Option<InstallManifest> {
+    InstallManifest::load(game_dir, InstallComponent::BepInEx)
+}
+
+/// Records that `release` was installed into `game_dir` as `files`.
+fn record_install(
+    game_dir: &Path,
+    release: &BepInExRelease,
+    files: &[String],
+) -> Result<(), BoxError> {
+    InstallManifest::create(
+        game_dir,
+        InstallComponent::BepInEx,
+        Some(release.version.clone()),
+        Some(release.sha1.clone()),
+        files,
+    )?
+    .save(game_dir)
+}
+
+/// Whether `installed` was made from `release`.
+fn is_release(installed: &InstallManifest, release: &BepInExRelease) -> bool {
+    installed.version.as_deref() == Some(release.version.as_str())
+        && installed
+            .artifact_sha1
+            .as_ref()
+            .is_some_and(|sha1| sha1.eq_ignore_ascii_case(&release.sha1))
+}
+
+/// Cleans up after the install `previous` once `release` was extracted into `game_dir` with
+/// `files`. Files of the old version the new one does not have are removed, as is everything
+/// generated by it. Plugins and configs are left alone.
+fn remove_previous_install(
+    game_dir: &Path,
+    previous: Option<&InstallManifest>,
+    release: &BepInExRelease,
+    files: &[String],
+) -> Result<(), BoxError> {
+    if previous.is_some_and(|previous| is_release(previous, release)) {
+        return Ok(());
+    }
+    match previous {
+        Some(previous) => log::info!(
+            "Replacing BepInEx {} with {}",
+            previous.version.as_deref().unwrap_or("unknown"),
+            release.version
+        ),
+        None => log::info!("Installing BepInEx {}", release.version),
+    }
+
+    // Windows paths are case-insensitive, so compare everything in lowercase
+    let kept: HashSet<String> = files.iter().map(|file| file.to_lowercase()).collect();
+    for file in previous.iter().flat_map(|previous| &previous.files) {
+        if kept.contains(&file.path.to_lowercase()) {
+            continue;
+        }
+        let path = game_dir.join(&file.path);
+        remove_file_if_exists(&path)?;
+        remove_empty_parents(game_dir, &path);
+    }
+    for dir in GENERATED_DIRS {
+        remove_dir_if_exists(&game_dir.join(dir))?;
+    }
+    Ok(())
+}
+
+/// Installs the zip at `zip_path` into `game_dir`. It has to be `release`, which is checked
+/// before anything is extracted. A different version installed before is replaced.
+pub fn install_bepinex(
+    game_dir: &Path,
+    zip_path: &Path,
+    release: &BepInExRelease,
+    progress: &ProgressReporter,
+    cancel: &CancelToken,
+) -> Result<(), BoxError> {
+    verify_file(zip_path, &release.file_name(), &release.sha1)?;
+    let previous = installed_bepinex(game_dir);
+    let files = extract_zip(zip_path, game_dir, MAX_EXTRACTED_SIZE, progress, cancel)?;
+    remove_previous_install(game_dir, previous.as_ref(), release, &files)?;
+    record_install(game_dir, release, &files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::checksum::calculate_checksum;
+    use crate::commands::release::{BepInExFlavor, IntegrityError, DEFAULT_BEPINEX_VERSION};
+    use crate::test_utils::TempDir;
+    use std::fs;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn release(version: &str, sha1: &str) -> BepInExRelease {
+        BepInExRelease {
+            version: version.to_string(),
+            flavor: BepInExFlavor::Il2cppX64,
+            url: format!("https://builds.bepinex.dev/{}.zip", version),
+            sha1: sha1.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_upgrade_removes_old_files() {
+        let tmp = TempDir::new("bepinex-upgrade");
+        let game = tmp.path();
+        let old_files = vec![
+            "winhttp.dll".to_string(),
+            "BepInEx/core/BepInEx.Core.dll".to_string(),
+            "BepInEx/core/Old.Only.dll".to_string(),
+            "dotnet/old/System.Old.dll".to_string(),
+        ];
+        for file in old_files
+            .iter()
+            .chain(["BepInEx/plugins/Lethe.dll".to_string()].iter())
+            .chain(["BepInEx/interop/Assembly-CSharp.dll".to_string()].iter())
+        {
+            fs::create_dir_all(game.join(file).parent().unwrap()).unwrap();
+            fs::write(game.join(file), file).unwrap();
+        }
+        let previous = release("6.0.0-be.733", "2AAE6C35C94FCFB415DBE95F408B9CE91EE846ED");
+        record_install(game, &previous, &old_files).unwrap();
+        let installed = installed_bepinex(game).unwrap();
+        assert_eq!(installed.version.as_deref(), Some("6.0.0-be.733"));
+        assert_eq!(installed.files.len(), 4);
+
+        // reinstalling the same version keeps everything
+        remove_previous_install(game, Some(&installed), &previous, &[]).unwrap();
+        assert!(game.join("BepInEx/core/Old.Only.dll").exists());
+        assert!(game.join("BepInEx/interop").exists());
+
+        let new_files = vec![
+            "winhttp.dll".to_string(),
+            "bepinex/core/BepInEx.Core.dll".to_string(),
+        ];
+        let newer = release("6.0.0-be.735", "0123456789ABCDEF0123456789ABCDEF01234567");
+        remove_previous_install(game, Some(&installed), &newer, &new_files).unwrap();
+        assert!(game.join("winhttp.dll").exists());
+        assert!(game.join("BepInEx/core/BepInEx.Core.dll").exists());
+        assert!(!game.join("BepInEx/core/Old.Only.dll").exists());
+        assert!(!game.join("dotnet").exists());
+        assert!(!game.join("BepInEx/interop").exists());
+        assert!(game.join("BepInEx/plugins/Lethe.dll").exists());
+    }
+
+    #[test]
+    fn test_unrecorded_install() {
+        let tmp = TempDir::new("bepinex-unrecorded");
+        let game = tmp.path();
+        assert!(installed_bepinex(game).is_none());
+
+        fs::create_dir_all(game.join("BepInEx/cache")).unwrap();
+        fs::write(game.join("BepInEx/zwei-bepinex.json"), b"not json").unwrap();
+        assert!(installed_bepinex(game).is_none());
+
+        let release = release(
+            DEFAULT_BEPINEX_VERSION,
+            "2AAE6C35C94FCFB415DBE95F408B9CE91EE846ED",
+        );
+        remove_previous_install(game, None, &release, &[]).unwrap();
+        assert!(!game.join("BepInEx/cache").exists());
+    }
+
+    #[test]
+    fn test_install_checks_zip() {
+        let tmp = TempDir::new("bepinex-install");
+        let game = tmp.path().join("game");
+        let zip_path = tmp.path().join("BepInEx.zip");
+        let mut zip = ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        zip.start_file("winhttp.dll", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"doorstop").unwrap();
+        zip.finish().unwrap();
+        let progress = ProgressReporter::with_sink("install-bepinex", "test", |_| {});
+        let cancel = CancelToken::default();
+
+        let wrong = release("6.0.0-be.733", "2AAE6C35C94FCFB415DBE95F408B9CE91EE846ED");
+        let err = install_bepinex(&game, &zip_path, &wrong, &progress, &cancel).unwrap_err();
+        assert!(err.downcast_ref::<IntegrityError>().is_some());
+        assert!(!game.exists());
+
+        let sha1 = calculate_checksum(&zip_path).unwrap();
+        let right = release("6.0.0-be.733", &sha1);
+        install_bepinex(&game, &zip_path, &right, &progress, &cancel).unwrap();
+        assert_eq!(fs::read(game.join("winhttp.dll")).unwrap(), b"doorstop");
+        let installed = installed_bepinex(&game).unwrap();
+        assert_eq!(installed.artifact_sha1, Some(sha1));
+        assert_eq!(installed.files[0].path, "winhttp.dll");
+    }
+}
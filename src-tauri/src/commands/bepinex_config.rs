@@ -0,0 +1,512 @@
+//! Reads and edits BepInEx.cfg without losing anything the writer does not understand.
+//!
+//! The file is kept as its lines, so comments, blank lines, ordering and line endings survive
+//! untouched. Only entries that are changed get rewritten. A typical entry looks like this:
+//!
+//! ```text
+//! [Logging.Console]
+//!
+//! ## Enables showing a console for log output.
+//! # Setting type: Boolean
+//! # Default value: false
+//! Enabled = true
+//! ```
+//!
+//! The `# Setting type` and `# Acceptable values` comments above an entry are used to check new
+//! values before they are written.
+
+use crate::commands::checksum::BoxError;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// Relative to the game folder.
+pub const CONFIG_PATH: &str = bepinex_path!("config/BepInEx.cfg");
+
+#[derive(Debug, Clone, PartialEq)]
+enum LineKind {
+    Section(String),
+    Entry {
+        key: String,
+        value: String,
+    },
+    Comment,
+    Blank,
+    /// Anything else, kept as it is.
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ConfigLine {
+    raw: String,
+    kind: LineKind,
+}
+
+impl ConfigLine {
+    fn parse(raw: &str) -> ConfigLine {
+        let line = raw.trim();
+        let kind = if line.is_empty() {
+            LineKind::Blank
+        } else if line.starts_with('#') {
+            LineKind::Comment
+        } else if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            LineKind::Section(section.trim().to_string())
+        } else if let Some((key, value)) = line.split_once('=') {
+            LineKind::Entry {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+            }
+        } else {
+            LineKind::Other
+        };
+        ConfigLine {
+            raw: raw.to_string(),
+            kind,
+        }
+    }
+
+    fn entry(key: &str, value: &str) -> ConfigLine {
+        ConfigLine::parse(&format!("{} = {}", key, value))
+    }
+}
+
+/// A setting with what the comments above it say about it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigEntry {
+    pub section: String,
+    pub key: String,
+    pub value: String,
+    pub description: Option<String>,
+    /// .NET type name, such as Boolean, Int32 or LogLevel.
+    pub setting_type: Option<String>,
+    pub default_value: Option<String>,
+    pub acceptable_values: Vec<String>,
+}
+
+impl ConfigEntry {
+    /// Checks that `value` fits the type of this setting, as far as it is known.
+    fn check(&self, value: &str) -> Result<(), BoxError> {
+        let valid = match self.setting_type.as_deref() {
+            Some("Boolean") => value == "true" || value == "false",
+            Some("Int32") => value.parse::<i32>().is_ok(),
+            Some("Int64") => value.parse::<i64>().is_ok(),
+            Some("Single") | Some("Double") => value.parse::<f64>().is_ok(),
+            _ if !self.acceptable_values.is_empty() => value
+                .split(',')
+                .all(|part| self.acceptable_values.iter().any(|v| v == part.trim())),
+            _ => true,
+        };
+        if !valid {
+            return Err(format!(
+                "{} is not a valid value for {}.{} ({})",
+                value,
+                self.section,
+                self.key,
+                self.setting_type.as_deref().unwrap_or("unknown type")
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// The content of a BepInEx.cfg.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BepInExConfig {
+    lines: Vec<ConfigLine>,
+    line_ending: &'static str,
+    trailing_newline: bool,
+}
+
+impl BepInExConfig {
+    pub fn parse(text: &str) -> BepInExConfig {
+        let line_ending = if text.contains("\r\n") { "\r\n" } else { "\n" };
+        let trailing_newline = text.ends_with('\n');
+        let body = text.strip_suffix('\n').unwrap_or(text);
+        let lines = if text.is_empty() {
+            Vec::new()
+        } else {
+            body.split('\n')
+                .map(|line| ConfigLine::parse(line.strip_suffix('\r').unwrap_or(line)))
+                .collect()
+        };
+        BepInExConfig {
+            lines,
+            line_ending,
+            trailing_newline,
+        }
+    }
+
+    /// The section every line belongs to, by line index.
+    fn sections(&self) -> Vec<Option<&str>> {
+        let mut current = None;
+        self.lines
+            .iter()
+            .map(|line| {
+                if let LineKind::Section(name) = &line.kind {
+                    current = Some(name.as_str());
+                }
+                current
+            })
+            .collect()
+    }
+
+    /// Index of the entry `key` in `section`.
+    fn find(&self, section: &str, key: &str) -> Option<usize> {
+        let sections = self.sections();
+        self.lines.iter().enumerate().position(|(i, line)| {
+            matches!(&line.kind, LineKind::Entry { key: k, .. } if k == key)
+                && sections[i] == Some(section)
+        })
+    }
+
+    /// Index of the first line of the comment block right above line `index`.
+    fn comment_start(&self, index: usize) -> usize {
+        let mut start = index;
+        while start > 0 && self.lines[start - 1].kind == LineKind::Comment {
+            start -= 1;
+        }
+        start
+    }
+
+    fn describe(&self, index: usize, section: &str) -> Option<ConfigEntry> {
+        let LineKind::Entry { key, value } = &self.lines[index].kind else {
+            return None;
+        };
+        let mut entry = ConfigEntry {
+            section: section.to_string(),
+            key: key.clone(),
+            value: value.clone(),
+            description: None,
+            setting_type: None,
+            default_value: None,
+            acceptable_values: Vec::new(),
+        };
+        let mut description: Vec<&str> = Vec::new();
+        for line in &self.lines[self.comment_start(index)..index] {
+            let comment = line.raw.trim();
+            if let Some(text) = comment.strip_prefix("##") {
+                description.push(text.trim());
+            } else if let Some(setting_type) = comment.strip_prefix("# Setting type:") {
+                entry.setting_type = Some(setting_type.trim().to_string());
+            } else if let Some(default) = comment.strip_prefix("# Default value:") {
+                entry.default_value = Some(default.trim().to_string());
+            } else if let Some(values) = comment.strip_prefix("# Acceptable values:") {
+                entry.acceptable_values = values.split(',').map(|v| v.trim().to_string()).collect();
+            }
+        }
+        if !description.is_empty() {
+            entry.description = Some(description.join("\n"));
+        }
+        Some(entry)
+    }
+
+    /// Every setting, in file order.
+    pub fn entries(&self) -> Vec<ConfigEntry> {
+        let sections = self.sections();
+        (0..self.lines.len())
+            .filter_map(|i| self.describe(i, sections[i]?))
+            .collect()
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        match &self.lines[self.find(section, key)?].kind {
+            LineKind::Entry { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Changes the existing setting `key` in `section`, checking `value` against its type. Values
+    /// have to fit on the setting's line, whatever its type.
+    pub fn set(&mut self, section: &str, key: &str, value: &str) -> Result<(), BoxError> {
+        if value.contains(['\n', '\r']) {
+            return Err(format!("{}.{} cannot span several lines", section, key).into());
+        }
+        let index = self
+            .find(section, key)
+            .ok_or_else(|| format!("BepInEx.cfg has no setting {}.{}", section, key))?;
+        if let Some(entry) = self.describe(index, section) {
+            entry.check(value)?;
+        }
+        self.lines[index] = ConfigLine::entry(key, value);
+        Ok(())
+    }
+
+    /// Adds `block`, an entry with its comments, at the end of `section`, creating the section if
+    /// needed.
+    fn insert(&mut self, section: &str, block: Vec<ConfigLine>) {
+        let sections = self.sections();
+        let Some(last) = sections.iter().rposition(|s| *s == Some(section)) else {
+            if self
+                .lines
+                .last()
+                .is_some_and(|line| line.kind != LineKind::Blank)
+            {
+                self.lines.push(ConfigLine::parse(""));
+            }
+            self.lines
+                .push(ConfigLine::parse(&format!("[{}]", section)));
+            self.lines.push(ConfigLine::parse(""));
+            self.lines.extend(block);
+            return;
+        };
+        let mut end = last + 1;
+        while end > 0 && self.lines[end - 1].kind == LineKind::Blank {
+            end -= 1;
+        }
+        let mut inserted = vec![ConfigLine::parse("")];
+        inserted.extend(block);
+        self.lines.splice(end..end, inserted);
+    }
+
+    /// Brings in the settings of `remote`, the recommended config, without losing local changes.
+    ///
+    /// Settings missing locally are added with their comments. A local setting takes the remote
+    /// value only if it still has the value of `base`, the recommended config it was installed
+    /// from, so values the user changed are kept. Without a base every local value is kept.
+    pub fn merge(&mut self, remote: &BepInExConfig, base: Option<&BepInExConfig>) {
+        let sections = remote.sections();
+        for (index, line) in remote.lines.iter().enumerate() {
+            let (LineKind::Entry { key, value }, Some(section)) = (&line.kind, sections[index])
+            else {
+                continue;
+            };
+            match self.find(section, key) {
+                Some(local) => {
+                    let untouched =
+                        base.and_then(|base| base.get(section, key)) == self.get(section, key);
+                    if untouched && self.get(section, key) != Some(value) {
+                        self.lines[local] = ConfigLine::entry(key, value);
+                    }
+                }
+                None => {
+                    let block = remote.lines[remote.comment_start(index)..=index].to_vec();
+                    self.insert(section, block);
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for BepInExConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = self
+            .lines
+            .iter()
+            .map(|line| line.raw.as_str())
+            .collect::<Vec<_>>()
+            .join(self.line_ending);
+        write!(f, "{}", text)?;
+        if self.trailing_newline {
+            write!(f, "{}", self.line_ending)?;
+        }
+        Ok(())
+    }
+}
+
+fn load(game_dir: &Path) -> Result<BepInExConfig, BoxError> {
+    let text = fs::read_to_string(game_dir.join(CONFIG_PATH))
+        .map_err(|e| format!("Failed to read BepInEx.cfg: {}", e))?;
+    Ok(BepInExConfig::parse(&text))
+}
+
+/// Writes the recommended config `remote` to `game_dir`. An existing config is merged with it
+/// instead of being replaced, see [`BepInExConfig::merge`]. `base` is the recommended config
+/// applied last time, if it is still known.
+pub fn apply_remote_config(
+    game_dir: &Path,
+    remote: &str,
+    base: Option<&str>,
+) -> Result<(), BoxError> {
+    let path = game_dir.join(CONFIG_PATH);
+    let remote = BepInExConfig::parse(remote);
+    let config = match fs::read_to_string(&path) {
+        Ok(text) => {
+            let mut local = BepInExConfig::parse(&text);
+            local.merge(&remote, base.map(BepInExConfig::parse).as_ref());
+            local
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => remote,
+        Err(err) => return Err(err.into()),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, config.to_string())?;
+    Ok(())
+}
+
+/// The settings in `./game`'s BepInEx.cfg, optionally only those of `section`.
+#[tauri::command]
+pub async fn get_bepinex_config(section: Option<String>) -> Result<Vec<ConfigEntry>, String> {
+    let config = load(Path::new("./game")).map_err(|e| e.to_string())?;
+    Ok(config
+        .entries()
+        .into_iter()
+        .filter(|entry| {
+            section
+                .as_ref()
+                .is_none_or(|section| entry.section == *section)
+        })
+        .collect())
+}
+
+/// Changes one setting in `./game`'s BepInEx.cfg, leaving the rest of the file as it is.
+#[tauri::command]
+pub async fn set_bepinex_config_value(
+    section: String,
+    key: String,
+    value: String,
+) -> Result<(), String> {
+    let game_dir = Path::new("./game");
+    let mut config = load(game_dir).map_err(|e| e.to_string())?;
+    config
+        .set(&section, &key, &value)
+        .map_err(|e| e.to_string())?;
+    fs::write(game_dir.join(CONFIG_PATH), config.to_string())
+        .map_err(|e| format!("Failed to write BepInEx.cfg: {}", e))?;
+    log::info!("Set {}.{} to {} in BepInEx.cfg", section, key, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    const CONFIG: &str = "[Caching]\r\n\
+        \r\n\
+        ## Enable/disable assembly metadata cache\r\n\
+        ## Required for loading plugins faster.\r\n\
+        # Setting type: Boolean\r\n\
+        # Default value: true\r\n\
+        EnableAssemblyCache = true\r\n\
+        \r\n\
+        [Logging.Console]\r\n\
+        \r\n\
+        ## Enables showing a console for log output.\r\n\
+        # Setting type: Boolean\r\n\
+        # Default value: false\r\n\
+        Enabled   =   false\r\n\
+        \r\n\
+        ## Which log levels to show in the console output.\r\n\
+        # Setting type: LogLevel\r\n\
+        # Default value: Fatal, Error, Warning, Message, Info\r\n\
+        # Acceptable values: None, Fatal, Error, Warning, Message, Info, Debug, All\r\n\
+        LogLevels = Fatal, Error, Warning, Message, Info\r\n";
+
+    #[test]
+    fn test_round_trip() {
+        let config = BepInExConfig::parse(CONFIG);
+        assert_eq!(config.to_string(), CONFIG);
+        let unix = CONFIG.replace("\r\n", "\n");
+        assert_eq!(BepInExConfig::parse(&unix).to_string(), unix);
+        assert_eq!(BepInExConfig::parse("").to_string(), "");
+    }
+
+    #[test]
+    fn test_typed_entries() {
+        let mut config = BepInExConfig::parse(CONFIG);
+        let entries = config.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0],
+            ConfigEntry {
+                section: "Caching".to_string(),
+                key: "EnableAssemblyCache".to_string(),
+                value: "true".to_string(),
+                description: Some(
+                    "Enable/disable assembly metadata cache\nRequired for loading plugins faster."
+                        .to_string()
+                ),
+                setting_type: Some("Boolean".to_string()),
+                default_value: Some("true".to_string()),
+                acceptable_values: Vec::new(),
+            }
+        );
+        assert_eq!(config.get("Logging.Console", "Enabled"), Some("false"));
+        assert_eq!(config.get("Caching", "Enabled"), None);
+
+        config.set("Logging.Console", "Enabled", "true").unwrap();
+        config
+            .set("Logging.Console", "LogLevels", "Fatal, Error, Debug")
+            .unwrap();
+        assert!(config.set("Logging.Console", "Enabled", "yes").is_err());
+        assert!(config
+            .set("Logging.Console", "LogLevels", "Verbose")
+            .is_err());
+        assert!(config.set("Logging.Console", "Missing", "true").is_err());
+        assert!(config
+            .set("Logging.Console", "Enabled", "true\r\n[Injected]")
+            .is_err());
+        assert_eq!(
+            config.to_string(),
+            CONFIG
+                .replace("Enabled   =   false", "Enabled = true")
+                .replace(
+                    "LogLevels = Fatal, Error, Warning, Message, Info\r\n",
+                    "LogLevels = Fatal, Error, Debug\r\n"
+                )
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_local_changes() {
+        let base = BepInExConfig::parse(CONFIG);
+        let mut local = base.clone();
+        local
+            .set("Caching", "EnableAssemblyCache", "false")
+            .unwrap();
+
+        let remote = BepInExConfig::parse(
+            &(CONFIG
+                .replace("Enabled   =   false", "Enabled = true")
+                .replace(
+                    "[Logging.Console]",
+                    "[Logging.Console]\r\n\r\n## Whether to write timestamps.\r\n# Setting type: Boolean\r\n# Default value: false\r\nTimestamps = true",
+                )
+                + "\r\n[Preloader]\r\n\r\n# Setting type: Boolean\r\nDumpAssemblies = false\r\n"),
+        );
+        local.merge(&remote, Some(&base));
+
+        // changed by the user, so kept
+        assert_eq!(local.get("Caching", "EnableAssemblyCache"), Some("false"));
+        // untouched, so updated
+        assert_eq!(local.get("Logging.Console", "Enabled"), Some("true"));
+        // new, so added with comments
+        let timestamps = local
+            .entries()
+            .into_iter()
+            .find(|entry| entry.key == "Timestamps")
+            .unwrap();
+        assert_eq!(timestamps.section, "Logging.Console");
+        assert_eq!(
+            timestamps.description.as_deref(),
+            Some("Whether to write timestamps.")
+        );
+        assert_eq!(local.get("Preloader", "DumpAssemblies"), Some("false"));
+        assert!(local.to_string().ends_with(
+            "LogLevels = Fatal, Error, Warning, Message, Info\r\n\r\n## Whether to write timestamps.\r\n# Setting type: Boolean\r\n# Default value: false\r\nTimestamps = true\r\n\r\n[Preloader]\r\n\r\n# Setting type: Boolean\r\nDumpAssemblies = false\r\n"
+        ));
+
+        // without a base nothing local is replaced
+        let mut local = base.clone();
+        local.merge(&remote, None);
+        assert_eq!(local.get("Logging.Console", "Enabled"), Some("false"));
+        assert_eq!(local.get("Logging.Console", "Timestamps"), Some("true"));
+    }
+
+    #[test]
+    fn test_apply_keeps_unreadable_config() {
+        let tmp = TempDir::new("bepinex-config-apply");
+        let path = tmp.path().join(CONFIG_PATH);
+        apply_remote_config(tmp.path(), CONFIG, None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), CONFIG);
+
+        // a config that cannot be read is not replaced by the remote one
+        fs::write(&path, b"[Caching]\r\nEnabled = \xff\r\n").unwrap();
+        assert!(apply_remote_config(tmp.path(), CONFIG, None).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"[Caching]\r\nEnabled = \xff\r\n");
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// Tokens of the operations currently running, by operation id.
+static RUNNING: LazyLock<Mutex<HashMap<String, CancelToken>>> = LazyLock::new(Default::default);
+
+/// Returned by an operation that stopped because it was cancelled.
+#[derive(Debug, PartialEq)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Operation was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// Flag shared between a running operation and whoever may cancel it.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fails with [`Cancelled`] once the operation was cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            return Err(Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// A registered long running operation. It can be cancelled by id until it is dropped.
+pub struct Operation {
+    id: String,
+    token: CancelToken,
+}
+
+impl Operation {
+    /// Registers a new operation under `id`, or under a generated id when none is given.
+    pub fn start(id: Option<String>) -> Result<Operation, String> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        let id = id.unwrap_or_else(|| format!("op-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed)));
+
+        let mut running = RUNNING.lock().unwrap();
+        if running.contains_key(&id) {
+            return Err(format!("Operation {} is already running", id));
+        }
+        let token = CancelToken::default();
+        running.insert(id.clone(), token.clone());
+        Ok(Operation { id, token })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Cancels the running operation `id`. Returns whether such an operation was running.
+pub fn cancel(id: &str) -> bool {
+    match RUNNING.lock().unwrap().get(id) {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Asks the operation started with `operation_id` to stop. It cleans up after itself and fails
+/// with "Operation was cancelled".
+#[tauri::command]
+pub async fn cancel_operation(operation_id: String) -> Result<bool, String> {
+    let cancelled = cancel(&operation_id);
+    if cancelled {
+        log::info!("Cancelling operation {}", operation_id);
+    }
+    Ok(cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_running_operation() {
+        let operation = Operation::start(Some("test-cancel".to_string())).unwrap();
+        assert!(Operation::start(Some("test-cancel".to_string())).is_err());
+        assert_eq!(operation.token().check(), Ok(()));
+
+        assert!(cancel("test-cancel"));
+        assert!(operation.token().is_cancelled());
+        assert_eq!(operation.token().check(), Err(Cancelled));
+
+        // finished operations can no longer be cancelled and their id can be reused
+        drop(operation);
+        assert!(!cancel("test-cancel"));
+        let operation = Operation::start(Some("test-cancel".to_string())).unwrap();
+        assert!(!operation.token().is_cancelled());
+
+        let generated = Operation::start(None).unwrap();
+        assert_ne!(generated.id(), Operation::start(None).unwrap().id());
+    }
+}
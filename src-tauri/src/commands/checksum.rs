@@ -1,52 +1,208 @@
+use crate::commands::cancel::CancelToken;
 use crate::commands::checksum::ManifestError::{
-    FileDoesNotExist, ImpossibleError, MismatchedContent, MismatchedType, UnknownFile,
+    FileDoesNotExist, ImpossibleError, MismatchedContent, MismatchedSize, MismatchedType,
+    UnknownFile, Unreadable,
 };
-use regex::Regex;
+use crate::commands::clone_strategy::{CloneReport, CloneStrategy, FileCloner};
+use crate::commands::hash_cache::HashCache;
+use crate::commands::manifest_parser;
+use crate::commands::manifest_parser::{FileFlags, ManifestEntry, ManifestHeader, ParsedManifest};
+use crate::commands::progress::ProgressReporter;
+use serde::Serialize;
 use sha1::{Digest, Sha1};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::fs;
-use std::fs::File;
+use std::fs::{File, Metadata};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 const FOLDER_SHA: &str = "0000000000000000000000000000000000000000";
 
+/// Size of the buffer used when hashing files.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of the chunks Steam splits depot files into, used to estimate the chunk count of
+/// generated manifests.
+const DEPOT_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Errors that can cross worker threads.
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
 #[derive(Debug)]
 struct FileInfo {
     size: u64,
     sha: String,
+    flags: FileFlags,
 }
 
 impl FileInfo {
     fn is_folder(&self) -> bool {
-        self.sha == FOLDER_SHA
+        self.sha == FOLDER_SHA || self.flags.is_directory()
     }
 }
 
-pub struct VersionManifest(HashMap<String, FileInfo>);
+pub struct VersionManifest(BTreeMap<String, FileInfo>);
 
-#[derive(Debug, PartialEq)]
+/// Tuning knobs for operations that walk the whole manifest.
+#[derive(Clone)]
+pub struct ManifestOptions {
+    /// Maximum number of files processed at the same time.
+    pub threads: usize,
+    /// Checksums of files that were already hashed, if any.
+    pub cache: Option<Arc<HashCache>>,
+    /// Where to report the files and bytes processed, if anywhere.
+    pub progress: Option<Arc<ProgressReporter>>,
+    /// Stops the operation early when cancelled.
+    pub cancel: Option<CancelToken>,
+    /// How files are placed into the destination folder when cloning.
+    pub strategy: CloneStrategy,
+}
+
+impl Default for ManifestOptions {
+    fn default() -> Self {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(4);
+        ManifestOptions {
+            threads,
+            cache: None,
+            progress: None,
+            cancel: None,
+            strategy: CloneStrategy::Copy,
+        }
+    }
+}
+
+impl ManifestOptions {
+    /// Default options, with the thread count overridden when one is given.
+    pub fn with_threads(threads: Option<usize>) -> Self {
+        let mut options = ManifestOptions::default();
+        if let Some(threads) = threads {
+            options.threads = threads.max(1);
+        }
+        options
+    }
+
+    pub fn with_cache(mut self, cache: Arc<HashCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Arc<ProgressReporter>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn with_strategy(mut self, strategy: CloneStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    fn check_cancelled(&self) -> Result<(), BoxError> {
+        match &self.cancel {
+            Some(cancel) => Ok(cancel.check()?),
+            None => Ok(()),
+        }
+    }
+
+    fn report<F>(&self, update: F)
+    where
+        F: FnOnce(&ProgressReporter),
+    {
+        if let Some(progress) = &self.progress {
+            update(progress);
+        }
+    }
+
+    /// Hashes `root/name`, reusing the cached checksum if the file has not changed since.
+    fn checksum(&self, root: &Path, name: &str, metadata: &Metadata) -> Result<String, BoxError> {
+        if let Some(sha) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get(root, name, metadata))
+        {
+            self.report(|progress| progress.add_bytes(metadata.len()));
+            return Ok(sha);
+        }
+
+        let sha = calculate_checksum_while(root.join(name), |chunk| {
+            self.check_cancelled()?;
+            self.report(|progress| progress.add_bytes(chunk.len() as u64));
+            Ok(())
+        })?;
+        if let Some(cache) = &self.cache {
+            cache.insert(root, name, metadata, sha.clone());
+        }
+        Ok(sha)
+    }
+
+    /// Runs `task` for every `(name, info)` item in parallel, reporting each file as it goes.
+    fn for_each_file<T, F>(&self, items: &[(&String, T)], task: F) -> Result<(), BoxError>
+    where
+        T: Sync,
+        F: Fn(&String, &T) -> Result<(), BoxError> + Sync,
+    {
+        for_each_parallel(items, self.threads, |(name, item)| {
+            self.check_cancelled()?;
+            self.report(|progress| progress.file_started(name));
+            task(name, item)?;
+            self.report(ProgressReporter::file_done);
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
 pub enum ManifestError {
     UnknownFile,
     FileDoesNotExist,
-    MismatchedType { wanted_dir: bool },
+    MismatchedType {
+        #[serde(rename = "wantedDir")]
+        wanted_dir: bool,
+    },
+    MismatchedSize {
+        expected: u64,
+        actual: u64,
+    },
     MismatchedContent,
+    Unreadable,
     ImpossibleError,
 }
 
+impl ManifestError {
+    /// Whether the error means the file exists but holds a different version than the manifest.
+    pub fn is_mismatch(&self) -> bool {
+        matches!(self, MismatchedSize { .. } | MismatchedContent)
+    }
+}
+
 impl Display for ManifestError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             UnknownFile => write!(f, "File is not list in the manifest"),
             MismatchedType { wanted_dir: true } => write!(f, "Expected directory but got a file"),
             MismatchedType { wanted_dir: false } => write!(f, "Expected file but got a directory"),
+            MismatchedSize { expected, actual } => write!(
+                f,
+                "File is {} bytes but should be {} bytes. Make sure your steam version is updated.",
+                actual, expected
+            ),
             MismatchedContent => write!(
                 f,
                 "File does not match the checksum. Make sure your steam version is updated."
             ),
+            Unreadable => write!(f, "File could not be read"),
             FileDoesNotExist => write!(f, "File should exist but does not"),
             ImpossibleError => write!(f, "Unknown error"),
         }
@@ -55,9 +211,9 @@ impl Display for ManifestError {
 
 impl Error for ManifestError {}
 
-fn calculate_checksum_while<F>(path: PathBuf, mut process: F) -> Result<String, Box<dyn Error>>
+fn calculate_checksum_while<F>(path: PathBuf, mut process: F) -> Result<String, BoxError>
 where
-    F: FnMut(&[u8]) -> Result<(), Box<dyn Error>>,
+    F: FnMut(&[u8]) -> Result<(), BoxError>,
 {
     // Open the file
     let mut file = File::open(&path)?;
@@ -66,7 +222,7 @@ where
     let mut hasher = Sha1::new();
 
     // Read the file in chunks
-    let mut buffer = [0u8; 8192]; // You can adjust the chunk size as needed
+    let mut buffer = vec![0u8; CHUNK_SIZE];
     loop {
         let bytes_read = file.read(&mut buffer)?;
         process(&buffer[..bytes_read])?;
@@ -83,67 +239,869 @@ where
     Ok(hex_digest)
 }
 
+/// Uppercase hex SHA-1 of the file at `path`, in the format used by manifests.
+pub fn calculate_checksum(path: &Path) -> Result<String, BoxError> {
+    calculate_checksum_while(path.to_path_buf(), |_| Ok(()))
+}
+
+/// Hands out the indices `0..len` in order to at most `threads` workers, until they run out or
+/// `stop` is set. A worker always finishes the index it is working on.
+fn spawn_workers<F>(len: usize, threads: usize, stop: &AtomicBool, work: F)
+where
+    F: Fn(usize) + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let workers = threads.clamp(1, len.max(1));
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= len {
+                        break;
+                    }
+                    work(index);
+                }
+            });
+        }
+    });
+}
+
+/// Runs `task` on every item using at most `threads` workers.
+///
+/// Items are handed out in order and no new item is started once one has failed, so the
+/// error returned is always the one of the earliest failing item, exactly like a sequential loop.
+fn for_each_parallel<T, F>(items: &[T], threads: usize, task: F) -> Result<(), BoxError>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<(), BoxError> + Sync,
+{
+    let failed = AtomicBool::new(false);
+    let errors = Mutex::new(Vec::new());
+
+    spawn_workers(items.len(), threads, &failed, |index| {
+        if let Err(err) = task(&items[index]) {
+            failed.store(true, Ordering::Relaxed);
+            errors.lock().unwrap().push((index, err));
+        }
+    });
+
+    match errors
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .min_by_key(|(index, _)| *index)
+    {
+        Some((_, err)) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Runs `task` on every item using at most `threads` workers and returns the results in the
+/// order of `items`.
+fn map_parallel<T, R, F>(items: &[T], threads: usize, task: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+    spawn_workers(items.len(), threads, &AtomicBool::new(false), |index| {
+        let result = task(&items[index]);
+        results.lock().unwrap().push((index, result));
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Copies `name` from `src_dir` to `dst_dir`, verifying the source against the manifest while
+/// copying. A partially written or invalid copy is removed again.
+fn copy_file(
+    src_dir: &Path,
+    dst_dir: &Path,
+    name: &str,
+    info: &FileInfo,
+    options: &ManifestOptions,
+) -> Result<(), BoxError> {
+    let src = src_dir.join(name);
+    let src_metadata = fs::metadata(src.clone())?;
+    if src_metadata.len() != info.size {
+        return Err(MismatchedSize {
+            expected: info.size,
+            actual: src_metadata.len(),
+        }
+        .into());
+    }
+
+    // the destination may be a hard link, which must not be written through to the source
+    let dst = dst_dir.join(name);
+    remove_file_if_exists(&dst)?;
+    let mut dst_file = File::create(&dst)?;
+
+    // verify integrity of src while copying to dst
+    let checksum = calculate_checksum_while(src, move |chunk| {
+        options.check_cancelled()?;
+        if !chunk.is_empty() {
+            dst_file.write_all(chunk)?;
+            options.report(|progress| progress.add_bytes(chunk.len() as u64));
+        } else {
+            dst_file.flush()?;
+        }
+        Ok(())
+    })
+    .and_then(|checksum| {
+        if checksum != info.sha {
+            return Err(MismatchedContent.into());
+        }
+        Ok(checksum)
+    });
+    let checksum = match checksum {
+        Ok(checksum) => checksum,
+        Err(err) => {
+            if let Err(remove_err) = fs::remove_file(&dst) {
+                log::warn!(
+                    "Failed to remove partial copy {}: {}",
+                    dst.display(),
+                    remove_err
+                );
+            }
+            return Err(err);
+        }
+    };
+
+    if let Some(cache) = &options.cache {
+        cache.insert(dst_dir, name, &fs::metadata(&dst)?, checksum);
+    }
+    Ok(())
+}
+
+/// Lists every file and folder below `dir` as `(relative path, is folder)`, sorted by path.
+/// Entries matching `exclude` (compared case-insensitively) and everything below them are skipped.
+fn walk_folder(dir: &Path, exclude: &[String]) -> Result<Vec<(String, bool)>, BoxError> {
+    let excluded: Vec<String> = exclude
+        .iter()
+        .map(|name| name.trim_end_matches('/').to_lowercase())
+        .collect();
+
+    let mut found = Vec::new();
+    let mut pending = vec![String::new()];
+    while let Some(parent) = pending.pop() {
+        for entry in fs::read_dir(dir.join(&parent))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = if parent.is_empty() {
+                name
+            } else {
+                format!("{}/{}", parent, name)
+            };
+            if excluded.contains(&path.to_lowercase()) {
+                continue;
+            }
+
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(path.clone());
+                found.push((path, true));
+            } else if file_type.is_file() {
+                found.push((path, false));
+            } else {
+                log::warn!("Skipping {}: not a regular file", path);
+            }
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// Describes the folder `dir` in the format of the Limbus manifest, hashing every file.
+pub fn generate_manifest(
+    dir: &Path,
+    exclude: &[String],
+    options: &ManifestOptions,
+) -> Result<ParsedManifest, BoxError> {
+    let found = walk_folder(dir, exclude)?;
+    let mut sizes = BTreeMap::new();
+    for (path, is_dir) in &found {
+        if !is_dir {
+            sizes.insert(path.clone(), fs::metadata(dir.join(path))?.len());
+        }
+    }
+    let files: Vec<(&String, u64)> = sizes.iter().map(|(path, size)| (path, *size)).collect();
+    options.report(|progress| {
+        let bytes = files.iter().map(|(_, size)| size).sum();
+        progress.start_phase("hash", files.len() as u64, bytes);
+    });
+
+    let shas = Mutex::new(BTreeMap::new());
+    options.for_each_file(&files, |path, _| {
+        let sha = options.checksum(dir, path, &fs::metadata(dir.join(path))?)?;
+        shas.lock().unwrap().insert(path.clone(), sha);
+        Ok(())
+    })?;
+    let mut shas = shas.into_inner().unwrap();
+
+    let entries: Vec<ManifestEntry> = found
+        .into_iter()
+        .map(|(name, is_dir)| {
+            if is_dir {
+                return ManifestEntry {
+                    size: 0,
+                    chunks: 0,
+                    sha: FOLDER_SHA.to_string(),
+                    flags: FileFlags(FileFlags::DIRECTORY),
+                    name,
+                };
+            }
+            ManifestEntry {
+                size: sizes[&name],
+                chunks: sizes[&name].div_ceil(DEPOT_CHUNK_SIZE),
+                sha: shas.remove(&name).unwrap_or_default(),
+                flags: FileFlags::default(),
+                name,
+            }
+        })
+        .collect();
+
+    let header = ManifestHeader {
+        total_files: Some(entries.len() as u64),
+        total_chunks: Some(entries.iter().map(|entry| entry.chunks).sum()),
+        total_bytes: Some(entries.iter().map(|entry| entry.size).sum()),
+        ..Default::default()
+    };
+    Ok(ParsedManifest { header, entries })
+}
+
+/// Path next to `dir`, named after it with `suffix` appended.
+pub fn sibling(dir: &Path, suffix: &str) -> PathBuf {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    dir.with_file_name(format!("{}.{}", name, suffix))
+}
+
+pub fn remove_file_if_exists(path: &Path) -> Result<(), BoxError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+pub fn remove_dir_if_exists(dir: &Path) -> Result<(), BoxError> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Copies the file or folder `src` to `dst`, including everything below it.
+fn copy_recursively(src: &Path, dst: &Path) -> Result<(), BoxError> {
+    if !fs::symlink_metadata(src)?.is_dir() {
+        fs::copy(src, dst)?;
+        return Ok(());
+    }
+
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        copy_recursively(&entry.path(), &dst.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+/// Replaces `dst_dir` with `staging_dir`. The current `dst_dir` is moved aside to `previous_dir`
+/// and only deleted once the new folder is in place, or moved back if that fails.
+fn swap_in(staging_dir: &Path, dst_dir: &Path, previous_dir: &Path) -> Result<(), BoxError> {
+    remove_dir_if_exists(previous_dir)?;
+    let had_previous = dst_dir.exists();
+    if had_previous {
+        fs::rename(dst_dir, previous_dir)?;
+    }
+
+    if let Err(err) = fs::rename(staging_dir, dst_dir) {
+        if had_previous {
+            if let Err(restore_err) = fs::rename(previous_dir, dst_dir) {
+                log::error!(
+                    "Failed to restore {} from {}: {}",
+                    dst_dir.display(),
+                    previous_dir.display(),
+                    restore_err
+                );
+            }
+        }
+        return Err(err.into());
+    }
+
+    if had_previous {
+        if let Err(err) = fs::remove_dir_all(previous_dir) {
+            log::warn!("Failed to remove {}: {}", previous_dir.display(), err);
+        }
+    }
+    Ok(())
+}
+
+/// What an incremental sync had to do to bring a folder up to date.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncReport {
+    pub copied_files: usize,
+    pub copied_bytes: u64,
+    pub skipped_files: usize,
+    pub skipped_bytes: u64,
+}
+
+/// A file or folder found in a game folder that the manifest does not know about.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanEntry {
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// Total size of `path`, including everything below it if it is a folder.
+fn disk_usage(path: &Path) -> Result<u64, BoxError> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += disk_usage(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+/// A single manifest entry that failed verification.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyProblem {
+    pub path: String,
+    pub error: ManifestError,
+    pub message: String,
+}
+
+/// Outcome of checking a whole folder against the manifest.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReport {
+    pub total_files: usize,
+    pub total_folders: usize,
+    pub total_bytes: u64,
+    pub valid: usize,
+    pub missing: usize,
+    pub wrong_type: usize,
+    pub wrong_size: usize,
+    pub wrong_hash: usize,
+    pub unreadable: usize,
+    pub problems: Vec<VerifyProblem>,
+}
+
+impl VerifyReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Files that change with nearly every game update, checked by the quick install check.
+pub const DEFAULT_SENTINELS: &[&str] = &[
+    "LimbusCompany_Data/StreamingAssets/aa/catalog.json",
+    "GameAssembly.dll",
+    "LimbusCompany_Data/il2cpp_data/Metadata/global-metadata.dat",
+    "LimbusCompany.exe",
+];
+
+/// Files Zwei patches after cloning, which are expected to differ from the manifest.
+const PATCHED_FILES: &[&str] = &["LimbusCompany.exe"];
+
+/// Which files an install check looks at.
+#[derive(Debug, Clone)]
+pub enum InstallCheckMode {
+    /// Only the given files, which is fast but may miss damaged files.
+    Sentinels(Vec<String>),
+    /// Every file of the manifest.
+    Full,
+}
+
+impl Default for InstallCheckMode {
+    fn default() -> Self {
+        InstallCheckMode::Sentinels(DEFAULT_SENTINELS.iter().map(|s| s.to_string()).collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InstallStatus {
+    UpToDate,
+    /// Files differ from the manifest, most likely because the game was updated.
+    Outdated,
+    /// Files are missing or unreadable while the rest matches the manifest.
+    Corrupted,
+    NotInstalled,
+}
+
+/// Result of an install check, with the problems that led to the status.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallCheck {
+    pub status: InstallStatus,
+    pub reasons: Vec<VerifyProblem>,
+}
+
+/// A file that differs between two versions of the manifest. Sizes are 0 on the side where the
+/// file does not exist.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestChange {
+    pub path: String,
+    pub old_size: u64,
+    pub new_size: u64,
+    pub size_delta: i64,
+}
+
+impl ManifestChange {
+    fn new(path: &str, old_size: u64, new_size: u64) -> ManifestChange {
+        ManifestChange {
+            path: path.to_string(),
+            old_size,
+            new_size,
+            size_delta: new_size as i64 - old_size as i64,
+        }
+    }
+}
+
+/// What changed between two versions of the manifest.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestDiff {
+    pub added: Vec<ManifestChange>,
+    pub removed: Vec<ManifestChange>,
+    pub modified: Vec<ManifestChange>,
+    /// Bytes that have to be copied to update from the old to the new version.
+    pub copy_bytes: u64,
+    /// How much bigger the game gets, negative if it shrinks.
+    pub size_delta: i64,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
 impl VersionManifest {
-    pub fn check_is_up_to_date(&self, game_dir: &Path) -> Result<bool, Box<dyn Error>> {
-        let catalog = "LimbusCompany_Data/StreamingAssets/aa/catalog.json";
-        match self.check_file(game_dir, catalog, |_| Ok(())) {
-            Ok(_) => Ok(true),
-            Err(e) if e.downcast_ref::<ManifestError>() == Some(&MismatchedContent) => Ok(false),
-            Err(e) => Err(e),
+    /// Parses the content of a `limbus-manifest.txt` file.
+    pub fn parse(text: &str) -> Result<VersionManifest, BoxError> {
+        Ok(manifest_parser::parse(text)?.into())
+    }
+
+    /// Loads a manifest from a local `limbus-manifest.txt` file.
+    pub fn from_file(path: &Path) -> Result<VersionManifest, BoxError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read manifest {}: {}", path.display(), e))?;
+        VersionManifest::parse(&text)
+    }
+
+    /// Writes the manifest to `path` in the `limbus-manifest.txt` format.
+    pub fn save(&self, path: &Path) -> Result<(), BoxError> {
+        fs::write(path, manifest_parser::write(&self.into()))?;
+        Ok(())
+    }
+
+    /// Checks whether the game in `game_dir` matches this manifest. Problems with single files
+    /// become reasons of the returned status. It is an error if the check is cancelled or there
+    /// is nothing to check, e.g. because none of the sentinels are in the manifest.
+    pub fn check_install(
+        &self,
+        game_dir: &Path,
+        mode: &InstallCheckMode,
+        options: &ManifestOptions,
+    ) -> Result<InstallCheck, BoxError> {
+        let entries: Vec<(&String, &FileInfo)> = match mode {
+            InstallCheckMode::Full => self.0.iter().collect(),
+            InstallCheckMode::Sentinels(names) => names
+                .iter()
+                .filter_map(|name| {
+                    let entry = self.0.get_key_value(name);
+                    if entry.is_none() {
+                        log::warn!("Ignoring sentinel {}: it is not in the manifest", name);
+                    }
+                    entry
+                })
+                .collect(),
+        };
+        let checked = entries.len();
+        if checked == 0 {
+            return Err("None of the files to check are in the manifest".into());
         }
+
+        let mut reasons = self.verify_entries(game_dir, entries, options)?.problems;
+        // patched files never match the manifest, so only check that they are there
+        reasons.retain(|reason| {
+            !(reason.error.is_mismatch() && PATCHED_FILES.contains(&reason.path.as_str()))
+        });
+
+        let missing = reasons
+            .iter()
+            .filter(|reason| reason.error == FileDoesNotExist)
+            .count();
+        let status = if !game_dir.is_dir() || missing == checked {
+            InstallStatus::NotInstalled
+        } else if reasons.iter().any(|reason| reason.error.is_mismatch()) {
+            InstallStatus::Outdated
+        } else if !reasons.is_empty() {
+            InstallStatus::Corrupted
+        } else {
+            InstallStatus::UpToDate
+        };
+        Ok(InstallCheck { status, reasons })
+    }
+
+    fn files(&self) -> Vec<(&String, &FileInfo)> {
+        self.0
+            .iter()
+            .filter(|(_, info)| !info.is_folder())
+            .collect()
+    }
+
+    /// Starts a progress phase covering the files among `entries`.
+    fn start_phase(options: &ManifestOptions, phase: &str, entries: &[(&String, &FileInfo)]) {
+        options.report(|progress| {
+            let files = entries.iter().filter(|(_, info)| !info.is_folder());
+            let (count, bytes) = files.fold((0, 0), |(count, bytes), (_, info)| {
+                (count + 1, bytes + info.size)
+            });
+            progress.start_phase(phase, count, bytes);
+        });
     }
 
-    pub fn copy_to_folder(&self, src_dir: &Path, dst_dir: &Path) -> Result<(), Box<dyn Error>> {
-        // create directories
+    fn create_folders(&self, dst_dir: &Path) -> Result<(), BoxError> {
         for (name, info) in &self.0 {
             if info.is_folder() {
                 let path = dst_dir.join(name);
                 std::fs::create_dir_all(path)?;
             }
         }
+        Ok(())
+    }
 
-        // copy files while verifying integrity
-        for (name, info) in &self.0 {
-            if info.is_folder() {
-                continue;
+    /// Copies or links every file of the manifest from `src_dir` to `dst_dir`, depending on
+    /// `options.strategy`, verifying each one.
+    pub fn copy_to_folder(
+        &self,
+        src_dir: &Path,
+        dst_dir: &Path,
+        options: &ManifestOptions,
+    ) -> Result<CloneReport, BoxError> {
+        self.create_folders(dst_dir)?;
+        Self::start_phase(options, "copy", &self.files());
+
+        let cloner = FileCloner::new(options.strategy);
+        options.for_each_file(&self.files(), |name, info| {
+            // linked files are never read, so verify the source up front
+            let dst = dst_dir.join(name);
+            let linked = cloner.should_link(name) && {
+                self.check_file(src_dir, name, options)?;
+                remove_file_if_exists(&dst)?;
+                cloner.link(&src_dir.join(name), &dst)
+            };
+
+            if linked {
+                if let Some(cache) = &options.cache {
+                    cache.insert(dst_dir, name, &fs::metadata(&dst)?, info.sha.clone());
+                }
+            } else {
+                // copy files while verifying integrity
+                copy_file(src_dir, dst_dir, name, info, options)?;
             }
+            cloner.record(linked, info.size);
+            Ok(())
+        })?;
+        Ok(cloner.into_report())
+    }
 
-            let src = src_dir.join(name.clone());
-            let src_metadata = fs::metadata(src.clone())?;
-            if src_metadata.len() != info.size {
-                return Err(MismatchedContent.into());
+    /// Compares the files of this manifest with those of a `newer` one. Folders are ignored.
+    pub fn diff(&self, newer: &VersionManifest) -> ManifestDiff {
+        let old: BTreeMap<&String, &FileInfo> = self.files().into_iter().collect();
+        let new: BTreeMap<&String, &FileInfo> = newer.files().into_iter().collect();
+
+        let mut diff = ManifestDiff::default();
+        for (name, info) in &new {
+            match old.get(name) {
+                None => diff.added.push(ManifestChange::new(name, 0, info.size)),
+                Some(old_info) if old_info.sha != info.sha || old_info.size != info.size => diff
+                    .modified
+                    .push(ManifestChange::new(name, old_info.size, info.size)),
+                Some(_) => continue,
+            }
+            diff.copy_bytes += info.size;
+        }
+        for (name, info) in &old {
+            if !new.contains_key(name) {
+                diff.removed.push(ManifestChange::new(name, info.size, 0));
             }
+        }
 
+        diff.size_delta = [&diff.added, &diff.removed, &diff.modified]
+            .into_iter()
+            .flatten()
+            .map(|change| change.size_delta)
+            .sum();
+        diff
+    }
+
+    /// Replaces `dst_dir` with a verified copy of `src_dir`, without ever leaving it half updated.
+    ///
+    /// The game is copied into a staging folder next to `dst_dir`, together with everything in
+    /// `dst_dir` the manifest does not know about, such as BepInEx. Only once every file was
+    /// copied and verified is the staging folder swapped in. If anything fails or the clone is
+    /// cancelled, `dst_dir` is left untouched.
+    pub fn clone_to_folder(
+        &self,
+        src_dir: &Path,
+        dst_dir: &Path,
+        options: &ManifestOptions,
+    ) -> Result<CloneReport, BoxError> {
+        let staging_dir = sibling(dst_dir, "staging");
+        remove_dir_if_exists(&staging_dir)?;
+        fs::create_dir_all(&staging_dir)?;
+        // the cache keys folders by canonical path, which can no longer be resolved once renamed
+        let staging_dir = staging_dir.canonicalize()?;
+
+        let staged = self
+            .copy_to_folder(src_dir, &staging_dir, options)
+            .and_then(|report| {
+                self.copy_unknown_files(dst_dir, &staging_dir, options)?;
+                swap_in(&staging_dir, dst_dir, &sibling(dst_dir, "previous"))?;
+                Ok(report)
+            });
+        let report = match staged {
+            Ok(report) => report,
+            Err(err) => {
+                if let Err(remove_err) = remove_dir_if_exists(&staging_dir) {
+                    log::warn!("Failed to remove {}: {}", staging_dir.display(), remove_err);
+                }
+                return Err(err);
+            }
+        };
+
+        if let Some(cache) = &options.cache {
+            cache.move_root(&staging_dir, dst_dir);
+        }
+        Ok(report)
+    }
+
+    /// Copies everything in `game_dir` that is not part of the manifest to `dst_dir`.
+    fn copy_unknown_files(
+        &self,
+        game_dir: &Path,
+        dst_dir: &Path,
+        options: &ManifestOptions,
+    ) -> Result<(), BoxError> {
+        if !game_dir.exists() {
+            return Ok(());
+        }
+
+        for orphan in self.find_orphans(game_dir, &[])? {
+            options.check_cancelled()?;
+            copy_recursively(&game_dir.join(&orphan.path), &dst_dir.join(&orphan.path))?;
+        }
+        Ok(())
+    }
+
+    /// Brings `dst_dir` in line with the manifest, copying from `src_dir` only the files that are
+    /// missing from `dst_dir` or differ from the manifest.
+    ///
+    /// If `dst_dir` was installed from the manifest `previous`, the files that changed since are
+    /// copied right away instead of being hashed first.
+    pub fn sync_to_folder(
+        &self,
+        src_dir: &Path,
+        dst_dir: &Path,
+        previous: Option<&VersionManifest>,
+        options: &ManifestOptions,
+    ) -> Result<SyncReport, BoxError> {
+        self.create_folders(dst_dir)?;
+        Self::start_phase(options, "sync", &self.files());
+        let changed: HashSet<String> = previous
+            .map(|previous| previous.diff(self))
+            .into_iter()
+            .flat_map(|diff| diff.added.into_iter().chain(diff.modified))
+            .map(|change| change.path)
+            .collect();
+
+        // files are both hashed and possibly copied, so report progress per file instead
+        let quiet = ManifestOptions {
+            progress: None,
+            ..options.clone()
+        };
+        let report = Mutex::new(SyncReport::default());
+        options.for_each_file(&self.files(), |name, info| {
             let dst = dst_dir.join(name);
-            let mut dst_file = File::create(&dst)?;
+            let up_to_date = if changed.contains(name) {
+                if dst.is_dir() {
+                    fs::remove_dir_all(&dst)?;
+                }
+                false
+            } else {
+                match self.check_file(dst_dir, name, &quiet) {
+                    Ok(()) => true,
+                    Err(e) => match e.downcast_ref::<ManifestError>() {
+                        Some(MismatchedType { .. }) => {
+                            fs::remove_dir_all(&dst)?;
+                            false
+                        }
+                        Some(FileDoesNotExist) => false,
+                        Some(e) if e.is_mismatch() => false,
+                        _ => return Err(e),
+                    },
+                }
+            };
 
-            // verify integrity of src while copying to dst
-            let checksum = calculate_checksum_while(src, move |chunk| {
-                if !chunk.is_empty() {
-                    dst_file.write_all(chunk)?;
+            if !up_to_date {
+                copy_file(src_dir, dst_dir, name, info, &quiet)?;
+            }
+            options.report(|progress| progress.add_bytes(info.size));
+
+            let mut report = report.lock().unwrap();
+            if up_to_date {
+                report.skipped_files += 1;
+                report.skipped_bytes += info.size;
+            } else {
+                report.copied_files += 1;
+                report.copied_bytes += info.size;
+            }
+            Ok(())
+        })?;
+
+        Ok(report.into_inner().unwrap())
+    }
+
+    /// Lists everything inside `game_dir` that is not part of the manifest.
+    ///
+    /// Entries matching `allowlist` (paths relative to `game_dir`, compared case-insensitively) and
+    /// everything below them are ignored. Unknown folders are reported once, without their content.
+    pub fn find_orphans(
+        &self,
+        game_dir: &Path,
+        allowlist: &[&str],
+    ) -> Result<Vec<OrphanEntry>, BoxError> {
+        // Windows paths are case-insensitive, so compare everything in lowercase
+        let known: HashSet<String> = self.0.keys().map(|name| name.to_lowercase()).collect();
+        let allowed: Vec<String> = allowlist
+            .iter()
+            .map(|name| name.trim_end_matches('/').to_lowercase())
+            .collect();
+
+        let mut orphans = Vec::new();
+        let mut pending = vec![String::new()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = fs::read_dir(game_dir.join(&dir))?.collect::<Result<Vec<_>, _>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+
+            for entry in entries {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let path = if dir.is_empty() {
+                    name
                 } else {
-                    dst_file.flush()?;
+                    format!("{}/{}", dir, name)
+                };
+                let lowercase = path.to_lowercase();
+                if allowed.contains(&lowercase) {
+                    continue;
                 }
-                Ok(())
-            })?;
 
-            if checksum != info.sha {
-                return Err(MismatchedContent.into());
+                let is_dir = entry.file_type()?.is_dir();
+                if known.contains(&lowercase) {
+                    if is_dir {
+                        pending.push(path);
+                    }
+                    continue;
+                }
+
+                orphans.push(OrphanEntry {
+                    size: disk_usage(&entry.path())?,
+                    path,
+                    is_dir,
+                });
             }
         }
 
-        Ok(())
+        orphans.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(orphans)
+    }
+
+    /// Checks every entry of the manifest against `game_dir` and reports all problems found,
+    /// instead of stopping at the first one. Only fails if the verification is cancelled.
+    pub fn verify_all(
+        &self,
+        game_dir: &Path,
+        options: &ManifestOptions,
+    ) -> Result<VerifyReport, BoxError> {
+        self.verify_entries(game_dir, self.0.iter().collect(), options)
     }
 
-    pub fn check_file<F>(
+    fn verify_entries(
+        &self,
+        game_dir: &Path,
+        entries: Vec<(&String, &FileInfo)>,
+        options: &ManifestOptions,
+    ) -> Result<VerifyReport, BoxError> {
+        Self::start_phase(options, "verify", &entries);
+        let results = map_parallel(&entries, options.threads, |(name, info)| {
+            options.check_cancelled()?;
+            if info.is_folder() {
+                return self.check_file(game_dir, name, options);
+            }
+            options.report(|progress| progress.file_started(name));
+            let result = self.check_file(game_dir, name, options);
+            options.report(ProgressReporter::file_done);
+            result
+        });
+        options.check_cancelled()?;
+
+        let mut report = VerifyReport::default();
+        for ((name, info), result) in entries.into_iter().zip(results) {
+            if info.is_folder() {
+                report.total_folders += 1;
+            } else {
+                report.total_files += 1;
+                report.total_bytes += info.size;
+            }
+
+            let Err(err) = result else {
+                report.valid += 1;
+                continue;
+            };
+            let message = err.to_string();
+            let error = match err.downcast::<ManifestError>() {
+                Ok(err) => *err,
+                Err(_) => Unreadable,
+            };
+            match error {
+                FileDoesNotExist => report.missing += 1,
+                MismatchedType { .. } => report.wrong_type += 1,
+                MismatchedSize { .. } => report.wrong_size += 1,
+                MismatchedContent => report.wrong_hash += 1,
+                _ => report.unreadable += 1,
+            }
+            report.problems.push(VerifyProblem {
+                path: name.clone(),
+                error,
+                message,
+            });
+        }
+        Ok(report)
+    }
+
+    /// Checks a single manifest entry, skipping the hashing of files found in the hash cache.
+    pub fn check_file(
         &self,
         game_dir: &Path,
         child: &str,
-        process: F,
-    ) -> Result<(), Box<dyn Error>>
-    where
-        F: FnMut(&[u8]) -> Result<(), Box<dyn Error>>,
-    {
+        options: &ManifestOptions,
+    ) -> Result<(), BoxError> {
         let info = match self.0.get(child) {
             None => return Err(UnknownFile.into()),
             Some(info) => info,
@@ -168,9 +1126,13 @@ impl VersionManifest {
         if path.is_file() {
             let metadata = fs::metadata(path.clone())?;
             if metadata.len() != info.size {
-                return Err(MismatchedContent.into());
+                return Err(MismatchedSize {
+                    expected: info.size,
+                    actual: metadata.len(),
+                }
+                .into());
             }
-            if calculate_checksum_while(path, process)? != info.sha {
+            if options.checksum(game_dir, child, &metadata)? != info.sha {
                 return Err(MismatchedContent.into());
             }
             return Ok(());
@@ -180,41 +1142,58 @@ impl VersionManifest {
     }
 }
 
-pub(crate) async fn get_manifest() -> Result<VersionManifest, Box<dyn Error>> {
-    let url = "https://api.lethelc.site/limbus-manifest.txt";
-    let response = reqwest::get(url).await?.text().await?;
-
-    let mut file_map = HashMap::new();
-
-    let header = Regex::new(r"^\s*Size\s*Chunks\s*File SHA\s*Flags Name\s*$").unwrap();
-    let mut after_header = false;
-
-    for line in response.lines() {
-        if after_header {
-            if line.len() < 70 {
-                continue;
-            }
-
-            let size_str = line[0..14].trim();
-            let sha = line[22..63].trim().to_string();
-            let file_name = line[69..].trim();
-            let size = u64::from_str(size_str)?;
+impl From<ParsedManifest> for VersionManifest {
+    fn from(parsed: ParsedManifest) -> Self {
+        let files = parsed
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let info = FileInfo {
+                    size: entry.size,
+                    sha: entry.sha,
+                    flags: entry.flags,
+                };
+                (entry.name, info)
+            })
+            .collect();
+        VersionManifest(files)
+    }
+}
 
-            file_map.insert(file_name.to_string(), FileInfo { size, sha });
+impl From<&VersionManifest> for ParsedManifest {
+    fn from(manifest: &VersionManifest) -> Self {
+        let entries = manifest
+            .0
+            .iter()
+            .map(|(name, info)| ManifestEntry {
+                size: info.size,
+                chunks: info.size.div_ceil(DEPOT_CHUNK_SIZE),
+                sha: info.sha.clone(),
+                flags: info.flags,
+                name: name.clone(),
+            })
+            .collect();
+        ParsedManifest {
+            header: ManifestHeader::default(),
+            entries,
         }
-
-        after_header |= header.is_match(line);
     }
-
-    Ok(VersionManifest(file_map))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::cancel::Cancelled;
+    use crate::commands::manifest_source::{fetch_manifest, MANIFEST_URL};
+    use crate::test_utils::TempDir;
+
+    async fn get_manifest() -> Result<VersionManifest, BoxError> {
+        let tmp = TempDir::new("manifest");
+        fetch_manifest(MANIFEST_URL, tmp.path()).await
+    }
 
     #[tokio::test]
-    async fn test_manifest_fetch() -> Result<(), Box<dyn Error>> {
+    async fn test_manifest_fetch() -> Result<(), BoxError> {
         let manifest = get_manifest().await?;
         manifest
             .0
@@ -259,11 +1238,652 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_check_file() -> Result<(), Box<dyn Error>> {
+    async fn test_check_file() -> Result<(), BoxError> {
         let manifest = get_manifest().await?;
         let src = PathBuf::from("Limbus Company/");
         let dst = PathBuf::from("/tmp/limbus-test/");
-        manifest.copy_to_folder(&src, &dst)?;
+        manifest.copy_to_folder(&src, &dst, &ManifestOptions::default())?;
         Ok(())
     }
+
+    /// Writes a small fake game install and returns the manifest describing it.
+    fn synthetic_install(root: &Path) -> VersionManifest {
+        let mut files = BTreeMap::new();
+        for dir in ["LimbusCompany_Data", "LimbusCompany_Data/StreamingAssets"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            files.insert(
+                dir.to_string(),
+                FileInfo {
+                    size: 0,
+                    sha: FOLDER_SHA.to_string(),
+                    flags: FileFlags(FileFlags::DIRECTORY),
+                },
+            );
+        }
+        for i in 0..40 {
+            let name = format!("LimbusCompany_Data/StreamingAssets/bundle_{:02}", i);
+            let content = vec![i as u8; 1000 + i * CHUNK_SIZE / 7];
+            fs::write(root.join(&name), &content).unwrap();
+            files.insert(
+                name,
+                FileInfo {
+                    size: content.len() as u64,
+                    sha: format!("{:X}", Sha1::digest(&content)),
+                    flags: FileFlags::default(),
+                },
+            );
+        }
+        VersionManifest(files)
+    }
+
+    fn outcome<T>(result: Result<T, BoxError>) -> Result<(), String> {
+        result.map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    fn problems(report: &VerifyReport) -> Vec<(&str, &ManifestError)> {
+        report
+            .problems
+            .iter()
+            .map(|p| (p.path.as_str(), &p.error))
+            .collect()
+    }
+
+    #[test]
+    fn test_parallel_copy_matches_sequential() {
+        let tmp = TempDir::new("parallel-copy");
+        let src = tmp.path().join("src");
+        let manifest = synthetic_install(&src);
+
+        let sequential = ManifestOptions::with_threads(Some(1));
+        let parallel = ManifestOptions::with_threads(Some(8));
+        manifest
+            .copy_to_folder(&src, &tmp.path().join("seq"), &sequential)
+            .unwrap();
+        manifest
+            .copy_to_folder(&src, &tmp.path().join("par"), &parallel)
+            .unwrap();
+
+        for (name, info) in manifest.files() {
+            let seq = fs::read(tmp.path().join("seq").join(name)).unwrap();
+            let par = fs::read(tmp.path().join("par").join(name)).unwrap();
+            assert_eq!(seq.len() as u64, info.size);
+            assert_eq!(seq, par, "{} differs between copies", name);
+        }
+        assert!(manifest
+            .verify_all(&tmp.path().join("par"), &parallel)
+            .unwrap()
+            .is_valid());
+    }
+
+    #[test]
+    fn test_parallel_errors_match_sequential() {
+        let tmp = TempDir::new("parallel-errors");
+        let src = tmp.path().join("src");
+        let manifest = synthetic_install(&src);
+
+        // corrupt one file and remove a later one
+        let corrupted = src.join("LimbusCompany_Data/StreamingAssets/bundle_07");
+        let mut content = fs::read(&corrupted).unwrap();
+        content[0] ^= 0xFF;
+        fs::write(&corrupted, content).unwrap();
+        fs::remove_file(src.join("LimbusCompany_Data/StreamingAssets/bundle_30")).unwrap();
+
+        let sequential = ManifestOptions::with_threads(Some(1));
+        for threads in [2, 4, 16] {
+            let parallel = ManifestOptions::with_threads(Some(threads));
+            assert_eq!(
+                problems(&manifest.verify_all(&src, &sequential).unwrap()),
+                problems(&manifest.verify_all(&src, &parallel).unwrap())
+            );
+            assert_eq!(
+                outcome(manifest.copy_to_folder(&src, &tmp.path().join("seq"), &sequential)),
+                outcome(manifest.copy_to_folder(&src, &tmp.path().join("par"), &parallel))
+            );
+        }
+        assert_eq!(
+            outcome(manifest.copy_to_folder(&src, &tmp.path().join("seq"), &sequential)),
+            Err(MismatchedContent.to_string())
+        );
+    }
+
+    #[test]
+    fn test_verify_all_reports_every_problem() {
+        let tmp = TempDir::new("verify-all");
+        let root = tmp.path();
+        let manifest = synthetic_install(root);
+        let assets = root.join("LimbusCompany_Data/StreamingAssets");
+
+        fs::remove_file(assets.join("bundle_01")).unwrap();
+        fs::remove_file(assets.join("bundle_02")).unwrap();
+        fs::create_dir(assets.join("bundle_02")).unwrap();
+        fs::write(assets.join("bundle_03"), b"short").unwrap();
+        let mut content = fs::read(assets.join("bundle_04")).unwrap();
+        content[10] ^= 0xFF;
+        fs::write(assets.join("bundle_04"), content).unwrap();
+
+        let report = manifest
+            .verify_all(root, &ManifestOptions::default())
+            .unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.total_files, 40);
+        assert_eq!(report.total_folders, 2);
+        assert_eq!(report.valid, 38);
+        assert_eq!(
+            (
+                report.missing,
+                report.wrong_type,
+                report.wrong_size,
+                report.wrong_hash
+            ),
+            (1, 1, 1, 1)
+        );
+
+        assert_eq!(
+            problems(&report),
+            vec![
+                (
+                    "LimbusCompany_Data/StreamingAssets/bundle_01",
+                    &FileDoesNotExist
+                ),
+                (
+                    "LimbusCompany_Data/StreamingAssets/bundle_02",
+                    &MismatchedType { wanted_dir: false }
+                ),
+                (
+                    "LimbusCompany_Data/StreamingAssets/bundle_03",
+                    &MismatchedSize {
+                        expected: 1000 + 3 * CHUNK_SIZE as u64 / 7,
+                        actual: 5
+                    }
+                ),
+                (
+                    "LimbusCompany_Data/StreamingAssets/bundle_04",
+                    &MismatchedContent
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sync_only_copies_changed_files() {
+        let tmp = TempDir::new("sync");
+        let src = tmp.path().join("src");
+        let dst = tmp.path().join("dst");
+        let manifest = synthetic_install(&src);
+        let options = ManifestOptions::default();
+        let total_bytes: u64 = manifest.files().iter().map(|(_, info)| info.size).sum();
+
+        let first = manifest.sync_to_folder(&src, &dst, None, &options).unwrap();
+        assert_eq!((first.copied_files, first.skipped_files), (40, 0));
+        assert_eq!(first.copied_bytes, total_bytes);
+
+        let assets = dst.join("LimbusCompany_Data/StreamingAssets");
+        fs::remove_file(assets.join("bundle_05")).unwrap();
+        fs::write(assets.join("bundle_06"), b"stale").unwrap();
+        fs::remove_file(assets.join("bundle_07")).unwrap();
+        fs::create_dir(assets.join("bundle_07")).unwrap();
+        let mut content = fs::read(assets.join("bundle_08")).unwrap();
+        content[0] ^= 0xFF;
+        fs::write(assets.join("bundle_08"), content).unwrap();
+
+        let second = manifest.sync_to_folder(&src, &dst, None, &options).unwrap();
+        assert_eq!((second.copied_files, second.skipped_files), (4, 36));
+        assert_eq!(second.copied_bytes + second.skipped_bytes, total_bytes);
+        assert!(manifest.verify_all(&dst, &options).unwrap().is_valid());
+
+        let third = manifest.sync_to_folder(&src, &dst, None, &options).unwrap();
+        assert_eq!((third.copied_files, third.skipped_bytes), (0, total_bytes));
+    }
+
+    #[test]
+    fn test_sync_copies_files_changed_since_previous_manifest() {
+        let tmp = TempDir::new("sync-previous");
+        let src = tmp.path().join("src");
+        let dst = tmp.path().join("dst");
+        let manifest = synthetic_install(&src);
+        let options = ManifestOptions::default();
+        manifest.sync_to_folder(&src, &dst, None, &options).unwrap();
+
+        // saved and loaded again, it still describes the same install
+        let saved = tmp.path().join("installed-manifest.txt");
+        manifest.save(&saved).unwrap();
+        let mut previous = VersionManifest::from_file(&saved).unwrap();
+        assert!(previous.diff(&manifest).is_empty());
+
+        let name = "LimbusCompany_Data/StreamingAssets/bundle_03";
+        previous.0.get_mut(name).unwrap().sha = "0".repeat(40);
+        let report = manifest
+            .sync_to_folder(&src, &dst, Some(&previous), &options)
+            .unwrap();
+        assert_eq!((report.copied_files, report.skipped_files), (1, 39));
+        assert!(manifest.verify_all(&dst, &options).unwrap().is_valid());
+    }
+
+    #[test]
+    fn test_clone_swaps_in_verified_copy() {
+        let tmp = TempDir::new("clone");
+        let src = tmp.path().join("src");
+        let dst = tmp.path().join("game");
+        let manifest = synthetic_install(&src);
+        let cache = Arc::new(HashCache::load(tmp.path().join("hash-cache.json")));
+        let options = ManifestOptions::default().with_cache(cache.clone());
+
+        // an older install with BepInEx next to it
+        let name = "LimbusCompany_Data/StreamingAssets/bundle_03";
+        fs::create_dir_all(dst.join("BepInEx/plugins")).unwrap();
+        fs::write(dst.join("BepInEx/plugins/Lethe.dll"), b"lethe").unwrap();
+        fs::create_dir_all(dst.join("LimbusCompany_Data/StreamingAssets")).unwrap();
+        fs::write(dst.join(name), b"old version").unwrap();
+
+        manifest.clone_to_folder(&src, &dst, &options).unwrap();
+        assert!(manifest.verify_all(&dst, &options).unwrap().is_valid());
+        assert_eq!(
+            fs::read(dst.join("BepInEx/plugins/Lethe.dll")).unwrap(),
+            b"lethe"
+        );
+        assert!(!tmp.path().join("game.staging").exists());
+        assert!(!tmp.path().join("game.previous").exists());
+
+        // checksums of the staged files carry over to the swapped in folder
+        let metadata = fs::metadata(dst.join(name)).unwrap();
+        assert!(cache.get(&dst, name, &metadata).is_some());
+    }
+
+    #[test]
+    fn test_clone_strategies() {
+        let tmp = TempDir::new("clone-strategy");
+        let src = tmp.path().join("src");
+        let mut manifest = synthetic_install(&src);
+        let exe = b"MZ".to_vec();
+        fs::write(src.join("LimbusCompany.exe"), &exe).unwrap();
+        manifest.0.insert(
+            "LimbusCompany.exe".to_string(),
+            FileInfo {
+                size: exe.len() as u64,
+                sha: format!("{:X}", Sha1::digest(&exe)),
+                flags: FileFlags::default(),
+            },
+        );
+        let total_bytes: u64 = manifest.files().iter().map(|(_, info)| info.size).sum();
+
+        // changing a source file in place shows whether the clone shares its storage
+        let shares_storage = |dst: &Path, name: &str| {
+            let before = fs::read(dst.join(name)).unwrap();
+            let mut file = File::options().write(true).open(src.join(name)).unwrap();
+            file.write_all(&[before[0] ^ 0xFF]).unwrap();
+            drop(file);
+            let shared = fs::read(dst.join(name)).unwrap() != before;
+            let mut file = File::options().write(true).open(src.join(name)).unwrap();
+            file.write_all(&before[..1]).unwrap();
+            shared
+        };
+        let bundle = "LimbusCompany_Data/StreamingAssets/bundle_00";
+
+        let dst = tmp.path().join("copy");
+        let report = manifest
+            .clone_to_folder(&src, &dst, &ManifestOptions::default())
+            .unwrap();
+        assert_eq!(
+            (report.used, report.copied_files),
+            (CloneStrategy::Copy, 41)
+        );
+        assert!(!shares_storage(&dst, bundle));
+
+        let dst = tmp.path().join("hardlink");
+        let options = ManifestOptions::default().with_strategy(CloneStrategy::Hardlink);
+        let report = manifest.clone_to_folder(&src, &dst, &options).unwrap();
+        assert_eq!(report.used, CloneStrategy::Hardlink);
+        assert_eq!((report.linked_files, report.copied_files), (40, 1));
+        assert_eq!(report.linked_bytes + report.copied_bytes, total_bytes);
+        assert!(shares_storage(&dst, bundle));
+        assert!(!shares_storage(&dst, "LimbusCompany.exe"));
+        assert!(manifest.verify_all(&dst, &options).unwrap().is_valid());
+
+        // copying over a linked file replaces the link instead of truncating the source
+        copy_file(&src, &dst, bundle, &manifest.0[bundle], &options).unwrap();
+        assert!(!shares_storage(&dst, bundle));
+        assert!(manifest.verify_all(&src, &options).unwrap().is_valid());
+
+        // reflinks are not supported everywhere, in which case the clone falls back to copying
+        let dst = tmp.path().join("reflink");
+        let options = ManifestOptions::default().with_strategy(CloneStrategy::Reflink);
+        let report = manifest.clone_to_folder(&src, &dst, &options).unwrap();
+        assert_eq!(report.requested, CloneStrategy::Reflink);
+        assert_eq!(
+            report.used == CloneStrategy::Copy,
+            report.fallback_reason.is_some()
+        );
+        assert_eq!(report.linked_files + report.copied_files, 41);
+        assert!(manifest.verify_all(&dst, &options).unwrap().is_valid());
+    }
+
+    #[test]
+    fn test_failed_clone_leaves_game_untouched() {
+        let tmp = TempDir::new("clone-failed");
+        let src = tmp.path().join("src");
+        let dst = tmp.path().join("game");
+        let manifest = synthetic_install(&src);
+        manifest
+            .clone_to_folder(&src, &dst, &ManifestOptions::default())
+            .unwrap();
+        fs::write(dst.join("winhttp.dll"), b"doorstop").unwrap();
+        let snapshot = |dir: &Path| -> Vec<(String, Vec<u8>)> {
+            let mut files: Vec<_> = manifest
+                .files()
+                .into_iter()
+                .map(|(name, _)| (name.clone(), fs::read(dir.join(name)).unwrap()))
+                .collect();
+            files.push((
+                "winhttp.dll".to_string(),
+                fs::read(dir.join("winhttp.dll")).unwrap(),
+            ));
+            files
+        };
+        let before = snapshot(&dst);
+
+        // a corrupted source fails verification halfway through the copy
+        let corrupted = src.join("LimbusCompany_Data/StreamingAssets/bundle_20");
+        let mut content = fs::read(&corrupted).unwrap();
+        content[0] ^= 0xFF;
+        fs::write(&corrupted, content).unwrap();
+        assert_eq!(
+            outcome(manifest.clone_to_folder(&src, &dst, &ManifestOptions::with_threads(Some(1)))),
+            Err(MismatchedContent.to_string())
+        );
+        assert_eq!(snapshot(&dst), before);
+        assert!(!tmp.path().join("game.staging").exists());
+
+        let cancel = CancelToken::default();
+        cancel.cancel();
+        let options = ManifestOptions::default().with_cancel(cancel);
+        assert_eq!(
+            outcome(manifest.clone_to_folder(&src, &dst, &options)),
+            Err(Cancelled.to_string())
+        );
+        assert_eq!(snapshot(&dst), before);
+        assert!(!tmp.path().join("game.staging").exists());
+    }
+
+    #[test]
+    fn test_generated_manifest_matches_install() {
+        let tmp = TempDir::new("generate");
+        let root = tmp.path().join("game");
+        let manifest = synthetic_install(&root);
+        fs::create_dir_all(root.join("BepInEx/plugins")).unwrap();
+        fs::write(root.join("BepInEx/plugins/Lethe.dll"), b"lethe").unwrap();
+        fs::write(root.join("LimbusCompany_Data/empty"), b"").unwrap();
+
+        let options = ManifestOptions::default();
+        let generated = generate_manifest(&root, &["bepinex/".to_string()], &options).unwrap();
+        assert_eq!(generated.header.total_files, Some(43));
+        let written = manifest_parser::write(&generated);
+        assert_eq!(manifest_parser::parse(&written).unwrap(), generated);
+
+        let empty = generated
+            .entries
+            .iter()
+            .find(|entry| entry.name == "LimbusCompany_Data/empty")
+            .unwrap();
+        assert_eq!((empty.size, empty.chunks), (0, 0));
+
+        let generated = VersionManifest::parse(&written).unwrap();
+        assert!(generated.verify_all(&root, &options).unwrap().is_valid());
+        let diff = manifest.diff(&generated);
+        assert_eq!(
+            diff.removed.len() + diff.added.len() + diff.modified.len(),
+            1
+        );
+        assert_eq!(diff.added[0].path, "LimbusCompany_Data/empty");
+    }
+
+    #[test]
+    fn test_install_status() {
+        let tmp = TempDir::new("install-status");
+        let root = tmp.path().join("game");
+        let mut manifest = synthetic_install(&root);
+        manifest.0.insert(
+            "LimbusCompany.exe".to_string(),
+            FileInfo {
+                size: 2,
+                sha: format!("{:X}", Sha1::digest(b"MZ")),
+                flags: FileFlags::default(),
+            },
+        );
+        // the exe is patched after cloning
+        fs::write(root.join("LimbusCompany.exe"), b"MZ patched").unwrap();
+
+        let assets = root.join("LimbusCompany_Data/StreamingAssets");
+        let sentinels = InstallCheckMode::Sentinels(vec![
+            "LimbusCompany_Data/StreamingAssets/bundle_00".to_string(),
+            "LimbusCompany_Data/StreamingAssets/bundle_01".to_string(),
+            "LimbusCompany.exe".to_string(),
+            "not/in/manifest.dll".to_string(),
+        ]);
+        let options = ManifestOptions::default();
+        let status = |mode: &InstallCheckMode| {
+            let check = manifest.check_install(&root, mode, &options).unwrap();
+            let paths: Vec<String> = check.reasons.into_iter().map(|r| r.path).collect();
+            (check.status, paths)
+        };
+        assert_eq!(status(&sentinels), (InstallStatus::UpToDate, vec![]));
+        assert_eq!(
+            status(&InstallCheckMode::Full),
+            (InstallStatus::UpToDate, vec![])
+        );
+
+        // only the full check notices files that are not sentinels
+        let original = fs::read(assets.join("bundle_30")).unwrap();
+        fs::write(assets.join("bundle_30"), b"new version").unwrap();
+        assert_eq!(status(&sentinels).0, InstallStatus::UpToDate);
+        assert_eq!(
+            status(&InstallCheckMode::Full),
+            (
+                InstallStatus::Outdated,
+                vec!["LimbusCompany_Data/StreamingAssets/bundle_30".to_string()]
+            )
+        );
+        fs::write(assets.join("bundle_30"), original).unwrap();
+
+        fs::remove_file(assets.join("bundle_01")).unwrap();
+        assert_eq!(
+            status(&sentinels),
+            (
+                InstallStatus::Corrupted,
+                vec!["LimbusCompany_Data/StreamingAssets/bundle_01".to_string()]
+            )
+        );
+
+        fs::remove_file(root.join("LimbusCompany.exe")).unwrap();
+        fs::write(assets.join("bundle_00"), b"new version").unwrap();
+        assert_eq!(status(&sentinels).0, InstallStatus::Outdated);
+
+        fs::remove_file(assets.join("bundle_00")).unwrap();
+        assert_eq!(status(&sentinels).0, InstallStatus::NotInstalled);
+        let missing = manifest
+            .check_install(
+                &tmp.path().join("missing"),
+                &InstallCheckMode::Full,
+                &options,
+            )
+            .unwrap();
+        assert_eq!(missing.status, InstallStatus::NotInstalled);
+
+        // checking nothing must not report the game as up to date
+        for names in [vec![], vec!["not/in/manifest.dll".to_string()]] {
+            let result =
+                manifest.check_install(&root, &InstallCheckMode::Sentinels(names), &options);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_manifest_diff() {
+        let manifest = |files: &[(&str, u64, &str)]| {
+            let mut entries = BTreeMap::new();
+            entries.insert(
+                "LimbusCompany_Data".to_string(),
+                FileInfo {
+                    size: 0,
+                    sha: FOLDER_SHA.to_string(),
+                    flags: FileFlags(FileFlags::DIRECTORY),
+                },
+            );
+            for (name, size, sha) in files {
+                let info = FileInfo {
+                    size: *size,
+                    sha: sha.to_string(),
+                    flags: FileFlags::default(),
+                };
+                entries.insert(name.to_string(), info);
+            }
+            VersionManifest(entries)
+        };
+        let old = manifest(&[
+            ("GameAssembly.dll", 100, "A"),
+            ("LimbusCompany.exe", 50, "B"),
+            ("LimbusCompany_Data/removed", 30, "C"),
+            ("LimbusCompany_Data/same_size", 20, "D"),
+        ]);
+        let new = manifest(&[
+            ("GameAssembly.dll", 120, "A2"),
+            ("LimbusCompany.exe", 50, "B"),
+            ("LimbusCompany_Data/added", 10, "E"),
+            ("LimbusCompany_Data/same_size", 20, "D2"),
+        ]);
+
+        assert!(old.diff(&old).is_empty());
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.added,
+            vec![ManifestChange::new("LimbusCompany_Data/added", 0, 10)]
+        );
+        assert_eq!(
+            diff.removed,
+            vec![ManifestChange::new("LimbusCompany_Data/removed", 30, 0)]
+        );
+        assert_eq!(
+            diff.modified,
+            vec![
+                ManifestChange::new("GameAssembly.dll", 100, 120),
+                ManifestChange::new("LimbusCompany_Data/same_size", 20, 20),
+            ]
+        );
+        assert_eq!(diff.modified[0].size_delta, 20);
+        assert_eq!(diff.copy_bytes, 150);
+        assert_eq!(diff.size_delta, 0);
+    }
+
+    #[test]
+    fn test_cancelled_copy_leaves_no_partial_files() {
+        let tmp = TempDir::new("cancel-copy");
+        let src = tmp.path().join("src");
+        let dst = tmp.path().join("dst");
+        let manifest = synthetic_install(&src);
+        let cancel = CancelToken::default();
+        let options = ManifestOptions::default().with_cancel(cancel.clone());
+        cancel.cancel();
+
+        let cancelled = Err(Cancelled.to_string());
+        assert_eq!(
+            outcome(manifest.copy_to_folder(&src, &dst, &options)),
+            cancelled
+        );
+        assert_eq!(outcome(manifest.verify_all(&src, &options)), cancelled);
+        assert_eq!(manifest.find_orphans(&dst, &[]).unwrap(), vec![]);
+        let (name, info) = manifest.files()[0];
+        assert_eq!(
+            outcome(copy_file(&src, &dst, name, info, &options)),
+            cancelled
+        );
+        assert!(!dst.join(name).exists());
+
+        // a copy that turns out to be corrupted is removed as well
+        fs::write(src.join(name), vec![0xFF; info.size as usize]).unwrap();
+        let options = ManifestOptions::default();
+        assert_eq!(
+            outcome(copy_file(&src, &dst, name, info, &options)),
+            Err(MismatchedContent.to_string())
+        );
+        assert!(!dst.join(name).exists());
+    }
+
+    #[test]
+    fn test_cached_check_skips_untouched_files() {
+        let tmp = TempDir::new("cached-check");
+        let root = tmp.path().join("game");
+        let manifest = synthetic_install(&root);
+        let cache = Arc::new(HashCache::load(tmp.path().join("hash-cache.json")));
+        let options = ManifestOptions::default().with_cache(cache.clone());
+        assert!(manifest.verify_all(&root, &options).unwrap().is_valid());
+
+        // corrupt a file without changing its size or modification time
+        let name = "LimbusCompany_Data/StreamingAssets/bundle_09";
+        let path = root.join(name);
+        let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+        let mut content = fs::read(&path).unwrap();
+        content[0] ^= 0xFF;
+        fs::write(&path, content).unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(mtime).unwrap();
+        drop(file);
+
+        // the cached checksum is trusted, a fresh check is not fooled
+        manifest.check_file(&root, name, &options).unwrap();
+        assert_eq!(
+            outcome(manifest.check_file(&root, name, &ManifestOptions::default())),
+            Err(MismatchedContent.to_string())
+        );
+
+        // touching the file invalidates the cached checksum
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(mtime + std::time::Duration::from_secs(5))
+            .unwrap();
+        drop(file);
+        assert_eq!(
+            outcome(manifest.check_file(&root, name, &options)),
+            Err(MismatchedContent.to_string())
+        );
+    }
+
+    #[test]
+    fn test_find_orphans() {
+        let tmp = TempDir::new("orphans");
+        let root = tmp.path();
+        let manifest = synthetic_install(root);
+        let allowlist = ["BepInEx/", "doorstop_config.ini", "winhttp.dll"];
+        assert_eq!(manifest.find_orphans(root, &allowlist).unwrap(), vec![]);
+
+        let assets = root.join("LimbusCompany_Data/StreamingAssets");
+        fs::write(assets.join("removed_bundle"), b"old").unwrap();
+        fs::create_dir_all(root.join("OldPlugins/nested")).unwrap();
+        fs::write(root.join("OldPlugins/nested/a.dll"), b"12345").unwrap();
+        fs::write(root.join("OldPlugins/b.dll"), b"12").unwrap();
+        fs::write(root.join("stale.dll"), b"").unwrap();
+        fs::create_dir_all(root.join("bepinex/plugins")).unwrap();
+        fs::write(root.join("bepinex/plugins/Lethe.dll"), b"lethe").unwrap();
+        fs::write(root.join("doorstop_config.ini"), b"").unwrap();
+        fs::write(root.join("WinHttp.dll"), b"").unwrap();
+
+        let orphans = manifest.find_orphans(root, &allowlist).unwrap();
+        assert_eq!(
+            orphans,
+            vec![
+                OrphanEntry {
+                    path: "LimbusCompany_Data/StreamingAssets/removed_bundle".to_string(),
+                    is_dir: false,
+                    size: 3,
+                },
+                OrphanEntry {
+                    path: "OldPlugins".to_string(),
+                    is_dir: true,
+                    size: 7,
+                },
+                OrphanEntry {
+                    path: "stale.dll".to_string(),
+                    is_dir: false,
+                    size: 0,
+                },
+            ]
+        );
+    }
 }
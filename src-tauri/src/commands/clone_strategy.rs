@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Files Zwei modifies after cloning. They must never share storage with the Steam install.
+const ALWAYS_COPIED: &[&str] = &["LimbusCompany.exe", bepinex_path!("")];
+
+/// How files are placed into the game folder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CloneStrategy {
+    /// Full byte copy of every file.
+    #[default]
+    Copy,
+    /// Hard link to the original file, sharing its storage.
+    Hardlink,
+    /// Copy-on-write clone of the original file, on filesystems that support it (Btrfs, XFS,
+    /// APFS, ReFS).
+    Reflink,
+}
+
+/// Which strategy a clone ended up using, and for how many files.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloneReport {
+    pub requested: CloneStrategy,
+    /// The requested strategy, or `Copy` if it turned out not to be supported.
+    pub used: CloneStrategy,
+    /// Why the requested strategy was abandoned, if it was.
+    pub fallback_reason: Option<String>,
+    pub copied_files: usize,
+    pub copied_bytes: u64,
+    pub linked_files: usize,
+    pub linked_bytes: u64,
+}
+
+/// Whether `name` always has to be a real copy, whatever the strategy.
+fn must_copy(name: &str) -> bool {
+    let name = name.to_lowercase();
+    ALWAYS_COPIED.iter().any(|copied| {
+        let copied = copied.to_lowercase();
+        match copied.strip_suffix('/') {
+            Some(dir) => name == dir || name.starts_with(&copied),
+            None => name == copied,
+        }
+    })
+}
+
+/// Places files using a [`CloneStrategy`], falling back to copying for every remaining file as
+/// soon as linking fails once. Can be shared between worker threads.
+pub struct FileCloner {
+    linking: AtomicBool,
+    report: Mutex<CloneReport>,
+}
+
+impl FileCloner {
+    pub fn new(strategy: CloneStrategy) -> FileCloner {
+        FileCloner {
+            linking: AtomicBool::new(strategy != CloneStrategy::Copy),
+            report: Mutex::new(CloneReport {
+                requested: strategy,
+                used: strategy,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Whether `name` should be linked rather than copied.
+    pub fn should_link(&self, name: &str) -> bool {
+        self.linking.load(Ordering::Relaxed) && !must_copy(name)
+    }
+
+    /// Links `dst` to `src`. Returns false if that failed and the file has to be copied instead.
+    pub fn link(&self, src: &Path, dst: &Path) -> bool {
+        let strategy = self.report.lock().unwrap().requested;
+        let result = match strategy {
+            CloneStrategy::Copy => return false,
+            CloneStrategy::Hardlink => fs::hard_link(src, dst),
+            CloneStrategy::Reflink => reflink_copy::reflink(src, dst),
+        };
+        let Err(err) = result else {
+            return true;
+        };
+
+        if self.linking.swap(false, Ordering::Relaxed) {
+            log::warn!("Cannot clone with {:?}, copying instead: {}", strategy, err);
+            let mut report = self.report.lock().unwrap();
+            report.used = CloneStrategy::Copy;
+            report.fallback_reason = Some(err.to_string());
+        }
+        false
+    }
+
+    pub fn record(&self, linked: bool, size: u64) {
+        let mut report = self.report.lock().unwrap();
+        if linked {
+            report.linked_files += 1;
+            report.linked_bytes += size;
+        } else {
+            report.copied_files += 1;
+            report.copied_bytes += size;
+        }
+    }
+
+    pub fn into_report(self) -> CloneReport {
+        self.report.into_inner().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modified_files_are_always_copied() {
+        assert!(must_copy("LimbusCompany.exe"));
+        assert!(must_copy("limbuscompany.exe"));
+        assert!(must_copy("BepInEx"));
+        assert!(must_copy("bepinex/plugins/Lethe.dll"));
+        assert!(!must_copy("BepInExtra.dll"));
+        assert!(!must_copy("LimbusCompany_Data/StreamingAssets/bundle_00"));
+
+        let cloner = FileCloner::new(CloneStrategy::Hardlink);
+        assert!(cloner.should_link("GameAssembly.dll"));
+        assert!(!cloner.should_link("LimbusCompany.exe"));
+        assert!(!FileCloner::new(CloneStrategy::Copy).should_link("GameAssembly.dll"));
+    }
+}
@@ -1,89 +1,915 @@
+use crate::commands::bepinex;
+use crate::commands::bepinex_config::{self, CONFIG_PATH};
+use crate::commands::cancel::{CancelToken, Operation};
+use crate::commands::checksum::BoxError;
+use crate::commands::download_cache::{
+    self, DownloadCache, DownloadCacheReport, DOWNLOAD_CACHE_DIR,
+};
+use crate::commands::file_utils::get_app_data_dir;
+use crate::commands::http::{self, HttpError};
+use crate::commands::install_manifest::{InstallComponent, InstallManifest, UninstallReport};
+use crate::commands::lethe::{self, LetheHistory, LetheVersion};
+use crate::commands::plugins::{self, PluginInfo};
+use crate::commands::progress::ProgressReporter;
+use crate::commands::release::{
+    self, BepInExFlavor, BepInExRelease, LetheRelease, DEFAULT_BEPINEX_VERSION,
+    LAST_LETHE_RELEASE_FILE,
+};
 use futures::stream::StreamExt;
-use reqwest::Client;
-use std::path::Path;
-use tokio::fs::File;
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::{Response, StatusCode};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
-use zip::read::ZipArchive;
 
+/// Holds the replaced Lethe versions, relative to the app data folder.
+const LETHE_HISTORY_DIR: &str = "lethe-versions";
+/// The recommended BepInEx.cfg applied last time, relative to the app data folder. It is kept out
+/// of the download cache so pruning the cache does not lose it.
+const CONFIG_BASE_FILE: &str = "BepInEx.base.cfg";
+
+/// Installs BepInEx `version` (build 733 by default) into `./game`. Versions that are not built
+/// in are looked up in the release index at `index_url`, if given. Files of a previously
+/// installed different version are removed, so this also up- or downgrades BepInEx.
 #[tauri::command]
-pub async fn download_and_extract_bepinex() -> Result<(), String> {
-    let url = "https://builds.bepinex.dev/projects/bepinex_be/733/BepInEx-Unity.IL2CPP-win-x64-6.0.0-be.733%2B995f049.zip";
-    let zip_path = "BepInEx-Unity.IL2CPP-win-x64-6.0.0-be.733+995f049.zip";
+pub async fn download_and_extract_bepinex(
+    app: AppHandle,
+    version: Option<String>,
+    flavor: Option<BepInExFlavor>,
+    index_url: Option<String>,
+    operation_id: Option<String>,
+) -> Result<(), String> {
     let extract_to = "./game";
+    let version = version.unwrap_or_else(|| DEFAULT_BEPINEX_VERSION.to_string());
+    let flavor = flavor.unwrap_or_default();
+    let release = release::find_bepinex_release(&version, flavor, index_url.as_deref())
+        .await
+        .map_err(|e| e.to_string())?;
+    let operation = Operation::start(operation_id)?;
+    let cancel = operation.token();
+    let progress = ProgressReporter::new(&app, "install-bepinex", operation.id());
+
+    let zip_path = download_cached(
+        &download_cache(&app)?,
+        &release.url,
+        &release.file_name(),
+        Some(&release.sha1),
+        &progress,
+        &cancel,
+    )
+    .await
+    .map_err(|e| format!("Error downloading file: {}", e))?;
+
+    bepinex::install_bepinex(
+        Path::new(extract_to),
+        &zip_path,
+        &release,
+        &progress,
+        &cancel,
+    )
+    .map_err(|e| format!("Failed to install BepInEx: {}", e))?;
+    progress.finish();
+    Ok(())
+}
 
-    download_file(url, zip_path)
+/// Installs BepInEx into `./game` from the zip at `zip_path`, for machines that cannot download
+/// it. The zip has to be the release `version` (build 733 by default), looked up like
+/// [`download_and_extract_bepinex`] does, and is checked just like a download.
+#[tauri::command]
+pub async fn install_bepinex_from_file(
+    app: AppHandle,
+    zip_path: String,
+    version: Option<String>,
+    flavor: Option<BepInExFlavor>,
+    index_url: Option<String>,
+    operation_id: Option<String>,
+) -> Result<(), String> {
+    let version = version.unwrap_or_else(|| DEFAULT_BEPINEX_VERSION.to_string());
+    let flavor = flavor.unwrap_or_default();
+    let release = release::find_bepinex_release(&version, flavor, index_url.as_deref())
         .await
-        .map_err(|e| format!("Error downloading file: {}", e))?;
-    unzip_file(zip_path, extract_to).map_err(|e| format!("Error unzipping file: {}", e))?;
+        .map_err(|e| e.to_string())?;
+    let operation = Operation::start(operation_id)?;
+    let progress = ProgressReporter::new(&app, "install-bepinex", operation.id());
+
+    log::info!("Installing BepInEx {} from {}", release.version, zip_path);
+    bepinex::install_bepinex(
+        Path::new("./game"),
+        Path::new(&zip_path),
+        &release,
+        &progress,
+        &operation.token(),
+    )
+    .map_err(|e| format!("Failed to install BepInEx: {}", e))?;
+    progress.finish();
     Ok(())
 }
 
+/// BepInEx releases that can be installed. The release index at `index_url` is only asked if
+/// given.
 #[tauri::command]
-pub async fn download_and_install_lethe() -> Result<(), String> {
-    let url = "https://api.lethelc.site/Lethe.dll";
-    let directory = "./game/bepinex/plugins";
-    let destination = format!("{}/Lethe.dll", directory);
+pub async fn list_bepinex_releases(
+    index_url: Option<String>,
+) -> Result<Vec<BepInExRelease>, String> {
+    Ok(release::bepinex_releases(index_url.as_deref()).await)
+}
+
+/// The BepInEx version installed in `./game`, if it is known.
+#[tauri::command]
+pub async fn get_installed_bepinex() -> Result<Option<String>, String> {
+    Ok(bepinex::installed_bepinex(Path::new("./game")).and_then(|installed| installed.version))
+}
+
+/// Removes the files `component` put into `./game`. Files the user changed since are kept and
+/// reported, unless `force` is set.
+#[tauri::command]
+pub async fn uninstall_component(
+    component: InstallComponent,
+    force: Option<bool>,
+) -> Result<UninstallReport, String> {
+    let game_dir = Path::new("./game");
+    let manifest = InstallManifest::load(game_dir, component)
+        .ok_or_else(|| format!("{:?} was not installed by Zwei", component))?;
+    let report = manifest
+        .uninstall(game_dir, force.unwrap_or(false))
+        .map_err(|e| format!("Failed to uninstall {:?}: {}", component, e))?;
+    log::info!(
+        "Uninstalled {:?}: removed {} files, {} were modified",
+        component,
+        report.removed.len(),
+        report.modified.len()
+    );
+    Ok(report)
+}
+
+/// What is known about the installed and available Lethe versions.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LetheStatus {
+    /// "unknown" if Lethe.dll was not installed by Zwei.
+    pub installed: Option<String>,
+    pub latest: String,
+    pub update_available: bool,
+    /// Versions that can be rolled back to, newest first.
+    pub previous: Vec<String>,
+}
+
+fn lethe_history(app: &AppHandle) -> Result<LetheHistory, String> {
+    Ok(LetheHistory::new(
+        get_app_data_dir(app)?.join(LETHE_HISTORY_DIR),
+    ))
+}
 
-    std::fs::create_dir_all(directory)
-        .map_err(|err| format!("Failed to create dirs recursively: {}", err))?;
+fn last_lethe_release_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(get_app_data_dir(app)?.join(LAST_LETHE_RELEASE_FILE))
+}
 
-    download_file(url, &destination)
+/// Fetches the current Lethe release and remembers it, so a DLL can still be checked against it
+/// when the index cannot be reached.
+async fn latest_lethe_release(app: &AppHandle) -> Result<LetheRelease, String> {
+    let latest = release::fetch_lethe_release()
         .await
-        .map_err(|e| format!("Failed to download the file: {}", e))?;
+        .map_err(|e| format!("Failed to look up the latest Lethe release: {}", e))?;
+    if let Err(err) = latest.save(&last_lethe_release_path(app)?) {
+        log::warn!("Failed to remember Lethe {}: {}", latest.version, err);
+    }
+    Ok(latest)
+}
 
-    let url = "https://lethelc.site/libraries/BepInEx.cfg";
-    let directory = "./game/bepinex/config";
-    let destination = format!("{}/BepInEx.cfg", directory);
+fn lethe_version(release: LetheRelease) -> LetheVersion {
+    LetheVersion {
+        version: release.version,
+        sha1: release.sha1,
+    }
+}
+
+/// Installs the latest Lethe release and the recommended BepInEx.cfg. Lethe is only downloaded
+/// if the installed version differs, unless `force` is set. The replaced version is kept for
+/// [`rollback_lethe`].
+#[tauri::command]
+pub async fn download_and_install_lethe(
+    app: AppHandle,
+    force: Option<bool>,
+    operation_id: Option<String>,
+) -> Result<(), String> {
+    let operation = Operation::start(operation_id)?;
+    let cancel = operation.token();
+    let progress = ProgressReporter::new(&app, "install-lethe", operation.id());
+    let lethe = match latest_lethe_release(&app).await {
+        Ok(latest) => latest,
+        Err(err) => {
+            // without the index only the release it listed last time can be checked against
+            let last = LetheRelease::load(&last_lethe_release_path(&app)?).ok_or_else(|| {
+                format!(
+                    "{}. Lethe.dll cannot be verified without it and was not installed",
+                    err
+                )
+            })?;
+            log::warn!("{}, using Lethe {} listed last time", err, last.version);
+            last
+        }
+    };
+    let cache = download_cache(&app)?;
+    let game_dir = Path::new("./game");
 
-    std::fs::create_dir_all(directory)
-        .map_err(|err| format!("Failed to create dirs recursively: {}", err))?;
+    let installed = lethe::installed_lethe(game_dir)
+        .map_err(|e| format!("Failed to read the installed Lethe version: {}", e))?;
+    let up_to_date =
+        installed.is_some_and(|installed| installed.sha1.eq_ignore_ascii_case(&lethe.sha1));
+    if up_to_date && !force.unwrap_or(false) {
+        log::info!("Lethe {} is already installed", lethe.version);
+    } else {
+        log::info!("Installing Lethe {}", lethe.version);
+        let downloaded = download_cached(
+            &cache,
+            &lethe.url,
+            &format!("Lethe-{}.dll", lethe.version),
+            Some(&lethe.sha1),
+            &progress,
+            &cancel,
+        )
+        .await
+        .map_err(|e| format!("Failed to download the file: {}", e))?;
+        lethe::install_lethe(
+            game_dir,
+            &lethe_history(&app)?,
+            &downloaded,
+            &lethe_version(lethe),
+        )
+        .map_err(|e| format!("Failed to install Lethe: {}", e))?;
+    }
 
-    download_file(url, &destination)
+    let url = "https://lethelc.site/libraries/BepInEx.cfg";
+    // the copy applied last time tells which local settings were changed by the user
+    let base_path = get_app_data_dir(&app)?.join(CONFIG_BASE_FILE);
+    let base = std::fs::read_to_string(&base_path).ok().or_else(|| {
+        // older versions only kept it in the download cache
+        let cached = cache.path("BepInEx.cfg").ok()?;
+        std::fs::read_to_string(cached).ok()
+    });
+    let downloaded = download_cached(&cache, url, "BepInEx.cfg", None, &progress, &cancel)
         .await
         .map_err(|e| format!("Failed to download the file: {}", e))?;
+    let remote = std::fs::read_to_string(downloaded)
+        .map_err(|e| format!("Failed to read the downloaded BepInEx.cfg: {}", e))?;
+    bepinex_config::apply_remote_config(game_dir, &remote, base.as_deref())
+        .map_err(|e| format!("Failed to install BepInEx.cfg: {}", e))?;
+    if let Err(err) = std::fs::write(&base_path, &remote) {
+        log::warn!("Failed to remember the recommended BepInEx.cfg: {}", err);
+    }
+    InstallManifest::create(
+        game_dir,
+        InstallComponent::Config,
+        None,
+        None,
+        &[CONFIG_PATH.to_string()],
+    )
+    .and_then(|manifest| manifest.save(game_dir))
+    .map_err(|e| format!("Failed to record the BepInEx.cfg install: {}", e))?;
 
+    progress.finish();
     Ok(())
 }
 
-async fn download_file(url: &str, destination: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let client = Client::new();
-    let response = client.get(url).send().await?;
-    let mut file = File::create(destination).await?;
+/// Installs the Lethe DLL at `dll_path` into `./game`, for machines that cannot download it. It
+/// has to be a release Zwei already knows the hash of: the latest one if the release index can be
+/// reached, the one the index listed last time, or a version kept to roll back to. Returns the
+/// version that was installed.
+#[tauri::command]
+pub async fn install_lethe_from_file(app: AppHandle, dll_path: String) -> Result<String, String> {
+    let mut known = Vec::new();
+    match latest_lethe_release(&app).await {
+        Ok(latest) => known.push(lethe_version(latest)),
+        Err(err) => log::warn!("{}, checking {} against known releases", err, dll_path),
+    }
+    if let Some(last) = LetheRelease::load(&last_lethe_release_path(&app)?) {
+        known.push(lethe_version(last));
+    }
+    known.extend(
+        lethe_history(&app)?
+            .versions()
+            .into_iter()
+            .filter(|kept| kept.version != lethe::UNKNOWN_VERSION),
+    );
+    let expected = lethe::identify_lethe(Path::new(&dll_path), &known)
+        .map_err(|e| format!("Failed to install Lethe: {}", e))?;
 
-    let mut content = response.bytes_stream();
-    while let Some(chunk) = content.next().await {
-        file.write_all(&chunk?).await?;
+    log::info!("Installing Lethe {} from {}", expected.version, dll_path);
+    lethe::install_lethe(
+        Path::new("./game"),
+        &lethe_history(&app)?,
+        Path::new(&dll_path),
+        &expected,
+    )
+    .map_err(|e| format!("Failed to install Lethe: {}", e))?;
+    Ok(expected.version)
+}
+
+/// Downloads the plugin DLL at `url` and installs it into `./game`. The download is checked
+/// against `sha1` if given.
+#[tauri::command]
+pub async fn install_plugin_from_url(
+    app: AppHandle,
+    url: String,
+    sha1: Option<String>,
+    operation_id: Option<String>,
+) -> Result<PluginInfo, String> {
+    let operation = Operation::start(operation_id)?;
+    let cancel = operation.token();
+    let progress = ProgressReporter::new(&app, "install-plugin", operation.id());
+    let file_name = reqwest::Url::parse(&url)
+        .ok()
+        .and_then(|parsed| parsed.path_segments()?.next_back().map(str::to_string))
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| format!("{} does not point to a plugin DLL", url))?;
+    plugins::check_file_name(&file_name).map_err(|e| e.to_string())?;
+
+    log::info!("Installing plugin {} from {}", file_name, url);
+    // kept apart from the artifacts Zwei downloads itself, which plugins must not replace
+    let downloaded = download_cached(
+        &download_cache(&app)?,
+        &url,
+        &format!("plugin-{}", file_name),
+        sha1.as_deref(),
+        &progress,
+        &cancel,
+    )
+    .await
+    .map_err(|e| format!("Failed to download the file: {}", e))?;
+    let plugin = plugins::install(Path::new("./game"), &downloaded, &file_name)
+        .map_err(|e| format!("Failed to install {}: {}", file_name, e))?;
+    progress.finish();
+    Ok(plugin)
+}
+
+/// Compares the installed Lethe with the latest release.
+#[tauri::command]
+pub async fn check_lethe_update(app: AppHandle) -> Result<LetheStatus, String> {
+    let latest = latest_lethe_release(&app).await?;
+    let installed = lethe::installed_lethe(Path::new("./game"))
+        .map_err(|e| format!("Failed to read the installed Lethe version: {}", e))?;
+    let previous = lethe_history(&app)?
+        .versions()
+        .into_iter()
+        .map(|kept| kept.version)
+        .collect();
+
+    let update_available = !installed
+        .as_ref()
+        .is_some_and(|installed| installed.sha1.eq_ignore_ascii_case(&latest.sha1));
+    let installed = installed.map(|installed| {
+        if update_available {
+            installed.version
+        } else {
+            latest.version.clone()
+        }
+    });
+    Ok(LetheStatus {
+        installed,
+        latest: latest.version,
+        update_available,
+        previous,
+    })
+}
+
+/// Puts back the kept Lethe `version`, or the one installed before the current one. Returns the
+/// version that is now installed.
+#[tauri::command]
+pub async fn rollback_lethe(app: AppHandle, version: Option<String>) -> Result<String, String> {
+    let restored = lethe_history(&app)?
+        .roll_back(Path::new("./game"), version.as_deref())
+        .map_err(|e| format!("Failed to roll back Lethe: {}", e))?;
+    log::info!("Rolled back to Lethe {}", restored.version);
+    Ok(restored.version)
+}
+
+/// Lists the download cache. If `max_bytes` is given, the oldest downloads are removed until the
+/// cache is no larger than that, along with the BepInEx zip older versions left next to Zwei.
+#[tauri::command]
+pub async fn prune_download_cache(
+    app: AppHandle,
+    max_bytes: Option<u64>,
+) -> Result<DownloadCacheReport, String> {
+    let cache = download_cache(&app)?;
+    let mut report = cache
+        .prune(max_bytes)
+        .map_err(|e| format!("Failed to prune the download cache: {}", e))?;
+    if max_bytes.is_some() {
+        let legacy = download_cache::remove_legacy_download(Path::new("."))
+            .map_err(|e| format!("Failed to remove the old BepInEx download: {}", e))?;
+        if let Some(size) = legacy {
+            report.removed_files += 1;
+            report.removed_bytes += size;
+        }
+        log::info!(
+            "Removed {} files ({} bytes) from the download cache",
+            report.removed_files,
+            report.removed_bytes
+        );
     }
+    Ok(report)
+}
 
-    Ok(())
+fn download_cache(app: &AppHandle) -> Result<DownloadCache, String> {
+    Ok(DownloadCache::new(
+        get_app_data_dir(app)?.join(DOWNLOAD_CACHE_DIR),
+    ))
+}
+
+/// Downloads `url` into `cache` as `name` and returns where it is. If `sha1` is given, a cached
+/// copy with that hash is used instead of downloading it again.
+async fn download_cached(
+    cache: &DownloadCache,
+    url: &str,
+    name: &str,
+    sha1: Option<&str>,
+    progress: &ProgressReporter,
+    cancel: &CancelToken,
+) -> Result<PathBuf, BoxError> {
+    if let Some(cached) = sha1.and_then(|sha1| cache.find(name, sha1)) {
+        log::info!("Using the cached {}", name);
+        return Ok(cached);
+    }
+    let path = cache.path(name)?;
+    download_file(url, &path.to_string_lossy(), sha1, progress, cancel).await?;
+    Ok(path)
 }
 
-fn unzip_file(zip_path: &str, extract_to: &str) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Unzipping to: {}", extract_to);
-    let file = std::fs::File::open(zip_path)?;
-    let mut archive = ZipArchive::new(file)?;
-    std::fs::create_dir_all(extract_to)?;
+/// Downloads `url` to `destination`.
+///
+/// The data is written to `<destination>.part` and only renamed into place once complete. A
+/// `.part` file left behind by an interrupted download is resumed with a Range request if the
+/// server supports it, which is also how a download that broke off is retried. The ETag or
+/// Last-Modified date of the download is kept next to the `.part` file and sent as If-Range, so
+/// a file that changed on the server in the meantime is downloaded again from the start.
+/// Cancelling the download removes the partial file.
+///
+/// If `expected_sha1` is given, the download is only moved to `destination` if it matches. A
+/// download that does not is deleted.
+async fn download_file(
+    url: &str,
+    destination: &str,
+    expected_sha1: Option<&str>,
+    progress: &ProgressReporter,
+    cancel: &CancelToken,
+) -> Result<(), BoxError> {
+    let part_path = format!("{}.part", destination);
+    let mut attempt = 1;
+    let result = loop {
+        progress.start_phase("download", 1, 0);
+        progress.file_started(destination);
+        let err = match download_to_part(url, &part_path, progress, cancel).await {
+            Ok(()) => break Ok(()),
+            Err(err) => err,
+        };
+        let interrupted = matches!(
+            err.downcast_ref::<HttpError>(),
+            Some(HttpError::Transfer { .. })
+        );
+        if !interrupted || attempt >= http::MAX_ATTEMPTS || cancel.is_cancelled() {
+            break Err(err);
+        }
+        let delay = http::backoff(attempt);
+        log::warn!("{}, resuming in {:?}", err, delay);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    };
+
+    if let Err(err) = result {
+        if cancel.is_cancelled() {
+            remove_partial_download(&part_path).await;
+        }
+        return Err(err);
+    }
+    if let Some(expected) = expected_sha1 {
+        let name = Path::new(destination)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| destination.to_string());
+        if let Err(err) = release::verify_file(Path::new(&part_path), &name, expected) {
+            remove_partial_download(&part_path).await;
+            return Err(err);
+        }
+    }
+    tokio::fs::rename(&part_path, destination).await?;
+    remove_partial_download(&part_path).await;
+
+    progress.file_done();
+    Ok(())
+}
 
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let file_path = Path::new(file.name());
-        let outpath = Path::new(extract_to).join(file_path);
+/// Where the validator of the partial download `part_path` is kept.
+fn validator_path(part_path: &str) -> String {
+    format!("{}.validator", part_path)
+}
 
-        // Ensure the path is valid and doesn't escape the intended directory.
-        if !outpath.starts_with(extract_to) {
-            return Err(format!("Unsafe path detected: {:?}", outpath).into());
+/// Removes the partial download `part_path` and its validator, if they are there.
+async fn remove_partial_download(part_path: &str) {
+    for path in [part_path.to_string(), validator_path(part_path)] {
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                log::warn!("Failed to remove partial download {}: {}", path, err)
+            }
+            _ => {}
         }
+    }
+}
 
-        if file.name().ends_with('/') {
-            std::fs::create_dir_all(&outpath)?;
-        } else {
-            if let Some(parent) = outpath.parent() {
-                std::fs::create_dir_all(parent)?;
+/// What identifies the version of the file in `response`, for If-Range. Weak ETags are not
+/// allowed there, so the Last-Modified date is used instead.
+fn response_validator(response: &Response) -> Option<String> {
+    let headers = response.headers();
+    headers
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| headers.get(LAST_MODIFIED)?.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Start of the range the server answered a Range request with.
+fn content_range_start(response: &Response) -> Option<u64> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (start, _) = range.strip_prefix("bytes ")?.split_once('-')?;
+    start.parse().ok()
+}
+
+/// Downloads `url` into `part_path`, continuing after the bytes already in it when they belong to
+/// the same version of the file.
+async fn download_to_part(
+    url: &str,
+    part_path: &str,
+    progress: &ProgressReporter,
+    cancel: &CancelToken,
+) -> Result<(), BoxError> {
+    let mut offset = tokio::fs::metadata(part_path)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    // without a validator there is no telling which version the partial file is from
+    let validator = match offset {
+        0 => None,
+        _ => tokio::fs::read_to_string(validator_path(part_path))
+            .await
+            .ok(),
+    };
+
+    let response = if let Some(validator) = &validator {
+        let ranged = http::send(url, |client| {
+            client
+                .get(url)
+                .header(RANGE, format!("bytes={}-", offset))
+                .header(IF_RANGE, validator.as_str())
+        })
+        .await;
+        match ranged {
+            Ok(response)
+                if response.status() != StatusCode::PARTIAL_CONTENT
+                    || content_range_start(&response) == Some(offset) =>
+            {
+                Some(response)
             }
-            let mut outfile = std::fs::File::create(&outpath)?;
-            std::io::copy(&mut file, &mut outfile)?;
+            Ok(_)
+            | Err(HttpError::Status {
+                status: StatusCode::RANGE_NOT_SATISFIABLE,
+                ..
+            }) => None,
+            Err(err) => return Err(err.into()),
+        }
+    } else {
+        None
+    };
+    let response = match response {
+        Some(response) => response,
+        None => http::send(url, |client| client.get(url)).await?,
+    };
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+    if offset > 0 && !resumed {
+        log::info!("Cannot resume {}, downloading it again", url);
+        offset = 0;
+    }
+
+    let mut file = if resumed {
+        log::info!("Resuming download of {} at {} bytes", url, offset);
+        OpenOptions::new().append(true).open(part_path).await?
+    } else {
+        match response_validator(&response) {
+            Some(validator) => tokio::fs::write(validator_path(part_path), validator).await?,
+            None => remove_partial_download(part_path).await,
         }
+        File::create(part_path).await?
+    };
+    let expected = response.content_length().map(|length| offset + length);
+    if let Some(expected) = expected {
+        progress.set_bytes_total(expected);
+    }
+    progress.add_bytes(offset);
+
+    let mut written = offset;
+    let mut content = response.bytes_stream();
+    while let Some(chunk) = content.next().await {
+        cancel.check()?;
+        let chunk = chunk.map_err(|err| HttpError::Transfer {
+            url: url.to_string(),
+            message: err.to_string(),
+        })?;
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+        progress.add_bytes(chunk.len() as u64);
     }
+    file.flush().await?;
 
+    if let Some(expected) = expected.filter(|expected| written != *expected) {
+        return Err(HttpError::Transfer {
+            url: url.to_string(),
+            message: format!("got {} of {} bytes", written, expected),
+        }
+        .into());
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::checksum::calculate_checksum;
+    use crate::commands::release::IntegrityError;
+    use crate::test_utils::TempDir;
+    use sha1::{Digest, Sha1};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Minimal HTTP server standing in for the download hosts. Records the Range header of every
+    /// request it receives.
+    struct TestServer {
+        url: String,
+        ranges: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    /// Serves `body` with [`etag`] as its ETag, honouring Range requests if `supports_range` is set
+    /// and an If-Range header matches. The first requests are answered with the `errors`
+    /// statuses, and the first response is cut off after `cut_after` bytes of the body, if given.
+    fn serve(
+        body: Vec<u8>,
+        supports_range: bool,
+        cut_after: Option<usize>,
+        errors: Vec<u16>,
+    ) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.zip", listener.local_addr().unwrap());
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let recorded = ranges.clone();
+
+        thread::spawn(move || {
+            for (index, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                let range = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: "))
+                    .map(str::to_string);
+                recorded.lock().unwrap().push(range.clone());
+                let same_version = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("if-range: "))
+                    .is_none_or(|validator| validator == etag(&body));
+                if let Some(status) = errors.get(index) {
+                    let page = "<html>error</html>";
+                    let _ = stream.write_all(
+                        format!(
+                            "HTTP/1.1 {} Error\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            status,
+                            page.len(),
+                            page
+                        )
+                        .as_bytes(),
+                    );
+                    continue;
+                }
+
+                let start = range
+                    .as_deref()
+                    .and_then(|range| range.strip_prefix("bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok())
+                    .filter(|_| supports_range && same_version);
+                let head = match start {
+                    Some(start) if start >= body.len() => {
+                        "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\n".to_string()
+                    }
+                    Some(start) => format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n",
+                        start,
+                        body.len() - 1,
+                        body.len(),
+                        body.len() - start
+                    ),
+                    None => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", body.len()),
+                };
+                let head = format!("{}ETag: {}\r\n", head, etag(&body));
+                let content = match start {
+                    Some(start) if start >= body.len() => &body[..0],
+                    Some(start) => &body[start..],
+                    None => &body[..],
+                };
+                let content = match cut_after {
+                    Some(cut) if index == 0 => &content[..cut],
+                    _ => content,
+                };
+                let _ = stream.write_all(format!("{}Connection: close\r\n\r\n", head).as_bytes());
+                let _ = stream.write_all(content);
+                let _ = stream.flush();
+            }
+        });
+        TestServer { url, ranges }
+    }
+
+    fn etag(body: &[u8]) -> String {
+        format!("\"{:x}\"", Sha1::digest(body))
+    }
+
+    fn body() -> Vec<u8> {
+        (0..50_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn reporter() -> ProgressReporter {
+        ProgressReporter::with_sink("download", "test", |_| {})
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_after_interruption() {
+        let tmp = TempDir::new("download-resume");
+        let destination = tmp.path().join("file.zip");
+        let destination = destination.to_str().unwrap();
+        let part = format!("{}.part", destination);
+        let server = serve(body(), true, Some(20_000), vec![]);
+        let cancel = CancelToken::default();
+
+        download_file(&server.url, destination, None, &reporter(), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(destination).unwrap(), body());
+        assert!(!Path::new(&part).exists());
+
+        // the retry continued where the first response broke off
+        let ranges = server.ranges.lock().unwrap();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0], None);
+        let resumed_at: u64 = ranges[1].as_ref().unwrap()["bytes=".len()..]
+            .trim_end_matches('-')
+            .parse()
+            .unwrap();
+        assert!(resumed_at > 0 && resumed_at <= 20_000);
+    }
+
+    #[tokio::test]
+    async fn test_download_checks_status() {
+        let tmp = TempDir::new("download-status");
+        let destination = tmp.path().join("Lethe.dll");
+        let destination = destination.to_str().unwrap();
+        let cancel = CancelToken::default();
+
+        // a missing file fails right away, without writing the error page anywhere
+        let server = serve(body(), true, None, vec![404]);
+        let err = download_file(&server.url, destination, None, &reporter(), &cancel)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().ends_with("returned 404 Not Found"));
+        assert!(!Path::new(destination).exists());
+        assert!(!Path::new(&format!("{}.part", destination)).exists());
+        assert_eq!(server.ranges.lock().unwrap().len(), 1);
+
+        // server errors are retried
+        let server = serve(body(), true, None, vec![503, 502]);
+        download_file(&server.url, destination, None, &reporter(), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(destination).unwrap(), body());
+        assert_eq!(server.ranges.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_download_verifies_hash() {
+        let tmp = TempDir::new("download-hash");
+        let destination = tmp.path().join("Lethe.dll");
+        let destination = destination.to_str().unwrap();
+        let cancel = CancelToken::default();
+        let expected = tmp.path().join("expected.dll");
+        std::fs::write(&expected, body()).unwrap();
+        let sha1 = calculate_checksum(&expected).unwrap();
+
+        let server = serve(body(), true, None, vec![]);
+        download_file(&server.url, destination, Some(&sha1), &reporter(), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(destination).unwrap(), body());
+
+        // a tampered download is neither installed nor kept around
+        std::fs::remove_file(destination).unwrap();
+        let mut tampered = body();
+        tampered[1234] ^= 0xFF;
+        let server = serve(tampered, true, None, vec![]);
+        let err = download_file(&server.url, destination, Some(&sha1), &reporter(), &cancel)
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<IntegrityError>().is_some());
+        assert!(err
+            .to_string()
+            .starts_with("Lethe.dll failed the integrity check"));
+        assert!(!Path::new(destination).exists());
+        assert!(!Path::new(&format!("{}.part", destination)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_does_not_resume_changed_file() {
+        let tmp = TempDir::new("download-changed");
+        let destination = tmp.path().join("Lethe.dll");
+        let destination = destination.to_str().unwrap();
+        let part = format!("{}.part", destination);
+        let cancel = CancelToken::default();
+
+        // left behind by an interrupted download of the previous build
+        let old = vec![7u8; 30_000];
+        std::fs::write(&part, &old[..20_000]).unwrap();
+        std::fs::write(validator_path(&part), etag(&old)).unwrap();
+
+        let server = serve(body(), true, None, vec![]);
+        download_file(&server.url, destination, None, &reporter(), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(destination).unwrap(), body());
+        assert_eq!(
+            server.ranges.lock().unwrap()[0],
+            Some("bytes=20000-".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_restarts_without_range_support() {
+        let tmp = TempDir::new("download-restart");
+        let destination = tmp.path().join("file.zip");
+        let destination = destination.to_str().unwrap();
+        let part = format!("{}.part", destination);
+        let cancel = CancelToken::default();
+
+        let validator = validator_path(&part);
+
+        // a stale partial file from a server that ignores ranges is thrown away
+        std::fs::write(&part, b"stale data").unwrap();
+        std::fs::write(&validator, etag(&body())).unwrap();
+        let server = serve(body(), false, None, vec![]);
+        download_file(&server.url, destination, None, &reporter(), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(destination).unwrap(), body());
+        assert_eq!(
+            server.ranges.lock().unwrap()[0],
+            Some("bytes=10-".to_string())
+        );
+        assert!(!Path::new(&validator).exists());
+
+        // so is one that does not say which version of the file it is from
+        std::fs::write(&part, b"stale data").unwrap();
+        let server = serve(body(), true, None, vec![]);
+        download_file(&server.url, destination, None, &reporter(), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(destination).unwrap(), body());
+        assert_eq!(server.ranges.lock().unwrap()[0], None);
+
+        // a partial file longer than the download is thrown away as well
+        std::fs::write(&part, vec![0u8; 60_000]).unwrap();
+        std::fs::write(&validator, etag(&body())).unwrap();
+        let server = serve(body(), true, None, vec![]);
+        download_file(&server.url, destination, None, &reporter(), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(destination).unwrap(), body());
+        assert_eq!(server.ranges.lock().unwrap().len(), 2);
+
+        // cancelling removes the partial file instead of keeping it for later
+        cancel.cancel();
+        let server = serve(body(), true, None, vec![]);
+        let destination = tmp.path().join("cancelled.zip");
+        let destination = destination.to_str().unwrap();
+        assert!(
+            download_file(&server.url, destination, None, &reporter(), &cancel)
+                .await
+                .is_err()
+        );
+        assert!(!Path::new(&format!("{}.part", destination)).exists());
+        assert!(!Path::new(destination).exists());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::commands::checksum::{calculate_checksum, remove_file_if_exists, BoxError};
+use serde::Serialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Holds downloaded artifacts, relative to the app data folder.
+pub const DOWNLOAD_CACHE_DIR: &str = "downloads";
+
+/// Where older versions downloaded the BepInEx zip to, relative to the working directory.
+pub const LEGACY_BEPINEX_ZIP: &str = "BepInEx-Unity.IL2CPP-win-x64-6.0.0-be.733+995f049.zip";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedDownload {
+    pub name: String,
+    pub size: u64,
+    /// Seconds since the unix epoch.
+    pub modified: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadCacheReport {
+    /// What is left in the cache, oldest first.
+    pub files: Vec<CachedDownload>,
+    pub total_bytes: u64,
+    pub removed_files: usize,
+    pub removed_bytes: u64,
+}
+
+/// Downloaded artifacts, kept so they do not have to be downloaded again.
+pub struct DownloadCache {
+    dir: PathBuf,
+}
+
+impl DownloadCache {
+    pub fn new(dir: PathBuf) -> DownloadCache {
+        DownloadCache { dir }
+    }
+
+    /// Where the artifact `name` is stored. Creates the cache folder if needed.
+    pub fn path(&self, name: &str) -> Result<PathBuf, BoxError> {
+        let path = self.entry_path(name)?;
+        fs::create_dir_all(&self.dir)?;
+        Ok(path)
+    }
+
+    /// Where the artifact `name` is stored. Names are partly taken from release indexes, so
+    /// anything but a plain file name is rejected rather than joined onto the cache folder.
+    fn entry_path(&self, name: &str) -> Result<PathBuf, BoxError> {
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(file_name)), None) if file_name == name => {
+                Ok(self.dir.join(name))
+            }
+            _ => Err(format!("Invalid download cache entry name: {:?}", name).into()),
+        }
+    }
+
+    /// The cached artifact `name`, if it has the SHA-1 `sha1`. A cached copy that does not is
+    /// removed.
+    pub fn find(&self, name: &str, sha1: &str) -> Option<PathBuf> {
+        let path = self.entry_path(name).ok()?;
+        let actual = calculate_checksum(&path).ok()?;
+        if actual.eq_ignore_ascii_case(sha1) {
+            return Some(path);
+        }
+        log::warn!(
+            "Discarding cached {}, it does not have the expected hash",
+            name
+        );
+        if let Err(err) = remove_file_if_exists(&path) {
+            log::warn!("Failed to remove cached {}: {}", name, err);
+        }
+        None
+    }
+
+    /// Everything in the cache, oldest first. Partial downloads are left out, they belong to a
+    /// download that may still be running.
+    fn entries(&self) -> Result<Vec<CachedDownload>, BoxError> {
+        let mut entries = Vec::new();
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in read_dir {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !metadata.is_file() || name.ends_with(".part") || name.ends_with(".part.validator") {
+                continue;
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs())
+                .unwrap_or_default();
+            entries.push(CachedDownload {
+                name,
+                size: metadata.len(),
+                modified,
+            });
+        }
+        entries.sort_by(|a, b| (a.modified, &a.name).cmp(&(b.modified, &b.name)));
+        Ok(entries)
+    }
+
+    /// Removes the oldest artifacts until the cache holds at most `max_bytes`. Nothing is
+    /// removed if no limit is given.
+    pub fn prune(&self, max_bytes: Option<u64>) -> Result<DownloadCacheReport, BoxError> {
+        let mut report = DownloadCacheReport::default();
+        let entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        for entry in entries {
+            if max_bytes.is_some_and(|max_bytes| total > max_bytes) {
+                remove_file_if_exists(&self.dir.join(&entry.name))?;
+                total -= entry.size;
+                report.removed_files += 1;
+                report.removed_bytes += entry.size;
+            } else {
+                report.total_bytes += entry.size;
+                report.files.push(entry);
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Removes the BepInEx zip older versions left in `working_dir`. Returns its size, if it was
+/// there.
+pub fn remove_legacy_download(working_dir: &Path) -> Result<Option<u64>, BoxError> {
+    let path = working_dir.join(LEGACY_BEPINEX_ZIP);
+    let Ok(metadata) = fs::metadata(&path) else {
+        return Ok(None);
+    };
+    remove_file_if_exists(&path)?;
+    Ok(Some(metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn write_aged(path: &Path, content: &[u8], age_secs: u64) {
+        fs::write(path, content).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(age_secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_find_checks_hash() {
+        let tmp = TempDir::new("download-cache-find");
+        let cache = DownloadCache::new(tmp.path().join("downloads"));
+        assert_eq!(cache.find("Lethe.dll", "00"), None);
+
+        let path = cache.path("Lethe.dll").unwrap();
+        fs::write(&path, b"hello world").unwrap();
+        let sha1 = "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed";
+        assert_eq!(cache.find("Lethe.dll", sha1), Some(path.clone()));
+
+        // a corrupted copy is thrown away
+        fs::write(&path, b"hello world!").unwrap();
+        assert_eq!(cache.find("Lethe.dll", sha1), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_rejects_names_outside_cache() {
+        let tmp = TempDir::new("download-cache-names");
+        let cache = DownloadCache::new(tmp.path().join("downloads"));
+        fs::write(tmp.path().join("Lethe.dll"), b"hello world").unwrap();
+        let sha1 = "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed";
+        for name in [
+            "../Lethe.dll",
+            "sub/Lethe.dll",
+            "..",
+            ".",
+            "",
+            "/etc/passwd",
+        ] {
+            assert!(cache.path(name).is_err(), "{:?}", name);
+            assert_eq!(cache.find(name, sha1), None, "{:?}", name);
+        }
+        assert!(tmp.path().join("Lethe.dll").exists());
+        assert!(cache.path("Lethe-1.2.3.dll").is_ok());
+    }
+
+    #[test]
+    fn test_prune_removes_oldest() {
+        let tmp = TempDir::new("download-cache-prune");
+        let cache = DownloadCache::new(tmp.path().to_path_buf());
+        write_aged(&tmp.path().join("old.zip"), &[0; 300], 300);
+        write_aged(&tmp.path().join("older.zip"), &[0; 200], 400);
+        write_aged(&tmp.path().join("new.dll"), &[0; 100], 100);
+        write_aged(&tmp.path().join("next.zip.part"), &[0; 50], 500);
+        write_aged(&tmp.path().join("next.zip.part.validator"), b"\"v1\"", 500);
+
+        let report = cache.prune(None).unwrap();
+        assert_eq!(report.total_bytes, 600);
+        assert_eq!(report.removed_files, 0);
+        let names: Vec<&str> = report.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["older.zip", "old.zip", "new.dll"]);
+
+        let report = cache.prune(Some(300)).unwrap();
+        assert_eq!((report.removed_files, report.removed_bytes), (2, 500));
+        assert_eq!(report.total_bytes, 100);
+        assert!(!tmp.path().join("old.zip").exists());
+        assert!(tmp.path().join("new.dll").exists());
+
+        cache.prune(Some(0)).unwrap();
+        assert_eq!(cache.prune(None).unwrap().files.len(), 0);
+        // partial downloads are never pruned
+        assert!(tmp.path().join("next.zip.part").exists());
+        assert!(tmp.path().join("next.zip.part.validator").exists());
+    }
+
+    #[test]
+    fn test_remove_legacy_download() {
+        let tmp = TempDir::new("download-cache-legacy");
+        assert_eq!(remove_legacy_download(tmp.path()).unwrap(), None);
+        fs::write(tmp.path().join(LEGACY_BEPINEX_ZIP), [0; 42]).unwrap();
+        assert_eq!(remove_legacy_download(tmp.path()).unwrap(), Some(42));
+        assert!(!tmp.path().join(LEGACY_BEPINEX_ZIP).exists());
+    }
+}
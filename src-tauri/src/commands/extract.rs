@@ -0,0 +1,290 @@
+use crate::commands::cancel::CancelToken;
+use crate::commands::checksum::{remove_dir_if_exists, sibling, BoxError};
+use crate::commands::progress::ProgressReporter;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path};
+use zip::read::ZipArchive;
+
+/// Upper bound for everything extracted from one archive. BepInEx unpacks to well below that.
+pub const MAX_EXTRACTED_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Unix file type bits of a symbolic link.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Why an archive was refused. Nothing of it is extracted in that case.
+#[derive(Debug, PartialEq)]
+pub enum ExtractError {
+    /// The entry would end up outside the target folder.
+    UnsafePath(String),
+    /// Links could point anywhere, so they are never extracted.
+    Symlink(String),
+    /// The archive unpacks to more than allowed.
+    TooLarge { limit: u64 },
+}
+
+impl Display for ExtractError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractError::UnsafePath(name) => write!(f, "Unsafe path in archive: {}", name),
+            ExtractError::Symlink(name) => write!(f, "Archive contains a symbolic link: {}", name),
+            ExtractError::TooLarge { limit } => {
+                write!(f, "Archive unpacks to more than {} bytes", limit)
+            }
+        }
+    }
+}
+
+impl Error for ExtractError {}
+
+/// Path of an entry relative to the target folder, using `/` as separator. Only plain
+/// components are accepted, so the entry cannot leave the folder. Backslashes count as
+/// separators too, as they do on Windows.
+fn entry_path(name: &str, enclosed: Option<&Path>) -> Result<String, ExtractError> {
+    let unsafe_path = || ExtractError::UnsafePath(name.to_string());
+    let enclosed = enclosed.ok_or_else(unsafe_path)?;
+    let mut parts = Vec::new();
+    for component in enclosed.components() {
+        let Component::Normal(part) = component else {
+            if component == Component::CurDir {
+                continue;
+            }
+            return Err(unsafe_path());
+        };
+        for part in part.to_string_lossy().split('\\') {
+            match part {
+                "" | "." => {}
+                ".." => return Err(unsafe_path()),
+                _ if part.contains(':') => return Err(unsafe_path()),
+                _ => parts.push(part.to_string()),
+            }
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+/// Moves everything in `src` into `dst`, replacing files that exist in both.
+fn move_into(src: &Path, dst: &Path) -> Result<(), BoxError> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            move_into(&entry.path(), &target)?;
+        } else {
+            fs::rename(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Extracts `zip_path` into `dst` and returns the extracted files, relative to it.
+///
+/// Every entry is checked before anything is written: paths must stay inside `dst`, links are
+/// refused and the archive may unpack to at most `max_size` bytes. Entries are extracted into a
+/// staging folder next to `dst` and only moved into it once all of them were extracted, so a
+/// refused or broken archive leaves `dst` untouched.
+pub fn extract_zip(
+    zip_path: &Path,
+    dst: &Path,
+    max_size: u64,
+    progress: &ProgressReporter,
+    cancel: &CancelToken,
+) -> Result<Vec<String>, BoxError> {
+    let mut archive = ZipArchive::new(File::open(zip_path)?)?;
+
+    let mut entries = Vec::with_capacity(archive.len());
+    let mut total: u64 = 0;
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        let path = entry_path(file.name(), file.enclosed_name().as_deref())?;
+        if file
+            .unix_mode()
+            .is_some_and(|mode| mode & S_IFMT == S_IFLNK)
+        {
+            return Err(ExtractError::Symlink(file.name().to_string()).into());
+        }
+        total = total.saturating_add(file.size());
+        if total > max_size {
+            return Err(ExtractError::TooLarge { limit: max_size }.into());
+        }
+        entries.push((path, file.is_dir()));
+    }
+
+    let staging_dir = sibling(dst, "extract");
+    remove_dir_if_exists(&staging_dir)?;
+    fs::create_dir_all(&staging_dir)?;
+    let result = extract_entries(
+        &mut archive,
+        &entries,
+        &staging_dir,
+        max_size,
+        progress,
+        cancel,
+    )
+    .and_then(|files| {
+        move_into(&staging_dir, dst)?;
+        Ok(files)
+    });
+    if let Err(err) = remove_dir_if_exists(&staging_dir) {
+        log::warn!("Failed to remove {}: {}", staging_dir.display(), err);
+    }
+    result
+}
+
+fn extract_entries(
+    archive: &mut ZipArchive<File>,
+    entries: &[(String, bool)],
+    staging_dir: &Path,
+    max_size: u64,
+    progress: &ProgressReporter,
+    cancel: &CancelToken,
+) -> Result<Vec<String>, BoxError> {
+    progress.start_phase("extract", entries.len() as u64, 0);
+
+    let mut files = Vec::new();
+    let mut remaining = max_size;
+    for (i, (path, is_dir)) in entries.iter().enumerate() {
+        cancel.check()?;
+        progress.file_started(path);
+        let outpath = staging_dir.join(path);
+        if *is_dir {
+            fs::create_dir_all(&outpath)?;
+        } else {
+            if let Some(parent) = outpath.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = archive.by_index(i)?;
+            let mut outfile = File::create(&outpath)?;
+            // the sizes in the archive were checked already, but they could be lying
+            let written = io::copy(&mut (&mut file).take(remaining + 1), &mut outfile)?;
+            if written > remaining {
+                return Err(ExtractError::TooLarge { limit: max_size }.into());
+            }
+            remaining -= written;
+
+            #[cfg(unix)]
+            if let Some(mode) = file.unix_mode() {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode & 0o755))?;
+            }
+            files.push(path.clone());
+        }
+        progress.file_done();
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// Writes an archive with the given files, without any of the checks done on extraction.
+    fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in files {
+            if name.ends_with('/') {
+                zip.add_directory(*name, SimpleFileOptions::default())
+                    .unwrap();
+            } else {
+                zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+                zip.write_all(content).unwrap();
+            }
+        }
+        zip.finish().unwrap();
+    }
+
+    fn extract(zip: &Path, dst: &Path, max_size: u64) -> Result<Vec<String>, BoxError> {
+        let progress = ProgressReporter::with_sink("install-bepinex", "test", |_| {});
+        extract_zip(zip, dst, max_size, &progress, &CancelToken::default())
+    }
+
+    fn refused(zip: &Path, dst: &Path, max_size: u64) -> ExtractError {
+        let err = extract(zip, dst, max_size).unwrap_err();
+        match err.downcast::<ExtractError>() {
+            Ok(err) => *err,
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let tmp = TempDir::new("extract");
+        let zip = tmp.path().join("BepInEx.zip");
+        let game = tmp.path().join("game");
+        fs::create_dir_all(game.join("BepInEx/plugins")).unwrap();
+        fs::write(game.join("BepInEx/plugins/Lethe.dll"), b"lethe").unwrap();
+        fs::write(game.join("winhttp.dll"), b"old").unwrap();
+        write_zip(
+            &zip,
+            &[
+                ("BepInEx/", b""),
+                ("BepInEx/core/BepInEx.Core.dll", b"core"),
+                ("./winhttp.dll", b"new"),
+            ],
+        );
+
+        let files = extract(&zip, &game, MAX_EXTRACTED_SIZE).unwrap();
+        assert_eq!(files, ["BepInEx/core/BepInEx.Core.dll", "winhttp.dll"]);
+        assert_eq!(fs::read(game.join("winhttp.dll")).unwrap(), b"new");
+        assert_eq!(
+            fs::read(game.join("BepInEx/core/BepInEx.Core.dll")).unwrap(),
+            b"core"
+        );
+        assert_eq!(
+            fs::read(game.join("BepInEx/plugins/Lethe.dll")).unwrap(),
+            b"lethe"
+        );
+        assert!(!tmp.path().join("game.extract").exists());
+    }
+
+    #[test]
+    fn test_malicious_archives_are_refused() {
+        let tmp = TempDir::new("extract-malicious");
+        let game = tmp.path().join("game");
+        let zip = tmp.path().join("evil.zip");
+
+        for name in [
+            "../evil.dll",
+            "BepInEx/../../evil.dll",
+            "BepInEx/../evil.dll",
+            "/etc/evil.dll",
+            "..\\evil.dll",
+            "C:\\evil.dll",
+        ] {
+            write_zip(&zip, &[("winhttp.dll", b"ok"), (name, b"evil")]);
+            assert_eq!(
+                refused(&zip, &game, MAX_EXTRACTED_SIZE),
+                ExtractError::UnsafePath(name.to_string())
+            );
+        }
+
+        let mut writer = ZipWriter::new(File::create(&zip).unwrap());
+        writer
+            .add_symlink("BepInEx", "/", SimpleFileOptions::default())
+            .unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            refused(&zip, &game, MAX_EXTRACTED_SIZE),
+            ExtractError::Symlink("BepInEx".to_string())
+        );
+
+        write_zip(&zip, &[("a.dll", &[0; 600]), ("b.dll", &[0; 600])]);
+        assert_eq!(
+            refused(&zip, &game, 1000),
+            ExtractError::TooLarge { limit: 1000 }
+        );
+
+        // nothing of a refused archive is extracted
+        assert!(!game.exists());
+        assert!(!tmp.path().join("evil.dll").exists());
+        assert!(!tmp.path().join("game.extract").exists());
+    }
+}
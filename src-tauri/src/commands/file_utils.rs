@@ -1,6 +1,28 @@
-use crate::commands::checksum;
+use crate::commands::cancel::Operation;
+use crate::commands::checksum::{
+    generate_manifest, InstallCheck, InstallCheckMode, ManifestDiff, ManifestOptions, OrphanEntry,
+    SyncReport, VerifyReport, VersionManifest,
+};
+use crate::commands::clone_strategy::{CloneReport, CloneStrategy};
+use crate::commands::hash_cache::{HashCache, HASH_CACHE_FILE};
+use crate::commands::manifest_parser;
+use crate::commands::manifest_source::{self, MANIFEST_URL};
+use crate::commands::plugins::PLUGINS_DIR;
+use crate::commands::progress::ProgressReporter;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::{fs, path::Path};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Entries of `./game` that are not part of Limbus but were put there on purpose by the mod loader.
+const GAME_FOLDER_ALLOWLIST: &[&str] = &[
+    bepinex_path!(""),
+    "dotnet/",
+    ".doorstop_version",
+    "changelog.txt",
+    "doorstop_config.ini",
+    "winhttp.dll",
+];
 
 pub struct CacheDirectories {
     pub local_app_data: PathBuf,
@@ -27,11 +49,60 @@ pub fn get_cache_directories() -> Result<CacheDirectories, String> {
     })
 }
 
-#[tauri::command]
-pub async fn clone_folder_to_game(src_path: String) -> Result<(), String> {
-    let src = Path::new(&src_path);
-    let dest = Path::new("./game");
+pub fn get_app_data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))
+}
+
+/// Loads the checksum cache kept in the app data folder.
+fn load_hash_cache(app: &AppHandle) -> Result<Arc<HashCache>, String> {
+    let data_dir = get_app_data_dir(app)?;
+    Ok(Arc::new(HashCache::load(data_dir.join(HASH_CACHE_FILE))))
+}
+
+fn save_hash_cache(cache: &HashCache) {
+    if let Err(err) = cache.save() {
+        log::warn!("Failed to save hash cache: {}", err);
+    }
+}
+
+/// Remembers `manifest` as the one `./game` now matches, for [`diff_limbus_manifests`] and the
+/// next sync.
+fn save_installed_manifest(app: &AppHandle, manifest: &VersionManifest) {
+    let saved = get_app_data_dir(app)
+        .map_err(Into::into)
+        .and_then(|dir| manifest_source::save_installed_manifest(&dir, manifest));
+    if let Err(err) = saved {
+        log::warn!("Failed to record the installed manifest: {}", err);
+    }
+}
+
+/// Loads the manifest from `manifest_path` if one is given. Otherwise downloads it, falling back
+/// to the last downloaded copy when offline and warning the user about it.
+async fn load_manifest(
+    app: &AppHandle,
+    manifest_path: Option<String>,
+) -> Result<VersionManifest, String> {
+    if let Some(path) = manifest_path {
+        log::info!("Using local manifest: {}", path);
+        return VersionManifest::from_file(Path::new(&path)).map_err(|e| e.to_string());
+    }
+
+    let loaded = manifest_source::load_manifest(MANIFEST_URL, &get_app_data_dir(app)?)
+        .await
+        .map_err(|e| e.to_string())?;
+    if let Some(warning) = loaded.warning {
+        log::warn!("{}", warning);
+        if let Err(err) = app.emit("manifest-warning", warning) {
+            log::error!("Failed to emit manifest warning: {}", err);
+        }
+    }
+    Ok(loaded.manifest)
+}
 
+/// Makes sure `src` looks like a Limbus Company install before copying from it.
+fn check_limbus_source(src: &Path) -> Result<(), String> {
     let limbus_path = src.join("LimbusCompany.exe");
     let limbus_data_path = src.join("LimbusCompany_data");
 
@@ -43,20 +114,256 @@ pub async fn clone_folder_to_game(src_path: String) -> Result<(), String> {
         return Err("LimbusCompany_Data not found in the source directory.".to_string());
     }
 
+    Ok(())
+}
+
+/// Clones the Limbus install at `src_path` into `./game`. With the hardlink or reflink strategy,
+/// unmodified files share storage with `src_path`; the report tells which strategy was used.
+#[tauri::command]
+pub async fn clone_folder_to_game(
+    app: AppHandle,
+    src_path: String,
+    threads: Option<usize>,
+    manifest_path: Option<String>,
+    operation_id: Option<String>,
+    strategy: Option<CloneStrategy>,
+) -> Result<CloneReport, String> {
+    let src = Path::new(&src_path);
+    let dest = Path::new("./game");
+    check_limbus_source(src)?;
+
     let src_path = PathBuf::from(src);
     let dst_path = PathBuf::from(dest);
-    let ok = checksum::get_manifest()
-        .await
-        .map_err(|e| e.to_string())?
-        .copy_to_folder(&src_path, &dst_path);
-    if ok.is_err() {
-        // remove LimbusCompany.exe if integrity failed to force the game to be properly copied before launch
-        if let Err(err) = fs::remove_file(dst_path) {
-            log::error!("Failed to delete LimbusCompany.exe on error: {}", err);
+    let cache = load_hash_cache(&app)?;
+    let operation = Operation::start(operation_id)?;
+    let progress = Arc::new(ProgressReporter::new(&app, "clone", operation.id()));
+    let options = ManifestOptions::with_threads(threads)
+        .with_cache(cache.clone())
+        .with_progress(progress.clone())
+        .with_cancel(operation.token())
+        .with_strategy(strategy.unwrap_or_default());
+    // the current ./game stays in place until the new copy is complete and verified
+    let manifest = load_manifest(&app, manifest_path).await?;
+    let report = manifest.clone_to_folder(&src_path, &dst_path, &options);
+    save_hash_cache(&cache);
+    progress.finish();
+
+    let report = report.map_err(|e| e.to_string())?;
+    save_installed_manifest(&app, &manifest);
+    log::info!(
+        "Cloned game folder with {:?}: linked {} files ({} bytes), copied {} files ({} bytes)",
+        report.used,
+        report.linked_files,
+        report.linked_bytes,
+        report.copied_files,
+        report.copied_bytes
+    );
+    Ok(report)
+}
+
+/// Repairs `./game` by copying only the files that are missing or differ from the manifest. Files
+/// that changed since the manifest `./game` was last installed from are copied without checking.
+#[tauri::command]
+pub async fn sync_folder_to_game(
+    app: AppHandle,
+    src_path: String,
+    threads: Option<usize>,
+    manifest_path: Option<String>,
+    operation_id: Option<String>,
+) -> Result<SyncReport, String> {
+    let src = PathBuf::from(src_path);
+    check_limbus_source(&src)?;
+
+    let cache = load_hash_cache(&app)?;
+    let operation = Operation::start(operation_id)?;
+    let progress = Arc::new(ProgressReporter::new(&app, "sync", operation.id()));
+    let options = ManifestOptions::with_threads(threads)
+        .with_cache(cache.clone())
+        .with_progress(progress.clone())
+        .with_cancel(operation.token());
+    let manifest = load_manifest(&app, manifest_path).await?;
+    let previous = manifest_source::installed_manifest(&get_app_data_dir(&app)?).ok();
+    let report = manifest.sync_to_folder(&src, Path::new("./game"), previous.as_ref(), &options);
+    save_hash_cache(&cache);
+    progress.finish();
+    let report = report.map_err(|e| e.to_string())?;
+    save_installed_manifest(&app, &manifest);
+    log::info!(
+        "Synced game folder: copied {} files ({} bytes), skipped {} files ({} bytes)",
+        report.copied_files,
+        report.copied_bytes,
+        report.skipped_files,
+        report.skipped_bytes
+    );
+    Ok(report)
+}
+
+/// Checks every file of the folder at `src_path` against the manifest and reports the broken ones.
+#[tauri::command]
+pub async fn verify_limbus_folder(
+    app: AppHandle,
+    src_path: String,
+    threads: Option<usize>,
+    manifest_path: Option<String>,
+    operation_id: Option<String>,
+) -> Result<VerifyReport, String> {
+    let src = PathBuf::from(src_path);
+    let cache = load_hash_cache(&app)?;
+    let operation = Operation::start(operation_id)?;
+    let progress = Arc::new(ProgressReporter::new(&app, "verify", operation.id()));
+    let options = ManifestOptions::with_threads(threads)
+        .with_cache(cache.clone())
+        .with_progress(progress.clone())
+        .with_cancel(operation.token());
+    let manifest = load_manifest(&app, manifest_path).await?;
+    let report = manifest.verify_all(&src, &options);
+    save_hash_cache(&cache);
+    progress.finish();
+    let report = report.map_err(|e| e.to_string())?;
+    if report.is_valid() {
+        log::info!(
+            "Verified {}: all {} entries valid",
+            src.display(),
+            report.valid
+        );
+    } else {
+        log::warn!(
+            "Verified {}: {} valid, {} broken",
+            src.display(),
+            report.valid,
+            report.problems.len()
+        );
+    }
+    Ok(report)
+}
+
+/// Lists what changed between two versions of the Limbus manifest. By default compares the
+/// manifest `./game` was last cloned or synced from with the current one. Installs made before
+/// that manifest was recorded are compared from the manifest downloaded last instead.
+#[tauri::command]
+pub async fn diff_limbus_manifests(
+    app: AppHandle,
+    old_manifest_path: Option<String>,
+    new_manifest_path: Option<String>,
+) -> Result<ManifestDiff, String> {
+    let old = match old_manifest_path {
+        Some(path) => VersionManifest::from_file(Path::new(&path)),
+        None => {
+            let cache_dir = get_app_data_dir(&app)?;
+            manifest_source::installed_manifest(&cache_dir).or_else(|err| {
+                log::info!(
+                    "No manifest recorded for ./game, diffing from the cached one: {}",
+                    err
+                );
+                manifest_source::cached_manifest(&cache_dir)
+            })
         }
     }
+    .map_err(|e| {
+        format!(
+            "Failed to load the manifest ./game was installed from: {}",
+            e
+        )
+    })?;
+    let new = load_manifest(&app, new_manifest_path).await?;
 
-    ok.map_err(|e| e.to_string())
+    let diff = old.diff(&new);
+    if diff.is_empty() {
+        log::info!("Manifest diff: no changes");
+        return Ok(diff);
+    }
+    log::info!(
+        "Manifest diff: {} added, {} removed, {} modified, {} bytes to copy",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.modified.len(),
+        diff.copy_bytes
+    );
+    Ok(diff)
+}
+
+/// Hashes the folder at `src_path` and writes a manifest describing it to `output_path`, in the
+/// same format as the Limbus manifest. Paths in `exclude` are left out, e.g. `BepInEx/`.
+#[tauri::command]
+pub async fn generate_limbus_manifest(
+    app: AppHandle,
+    src_path: String,
+    output_path: String,
+    exclude: Option<Vec<String>>,
+    threads: Option<usize>,
+    operation_id: Option<String>,
+) -> Result<(), String> {
+    let src = PathBuf::from(src_path);
+    let cache = load_hash_cache(&app)?;
+    let operation = Operation::start(operation_id)?;
+    let progress = Arc::new(ProgressReporter::new(&app, "generate", operation.id()));
+    let options = ManifestOptions::with_threads(threads)
+        .with_cache(cache.clone())
+        .with_progress(progress.clone())
+        .with_cancel(operation.token());
+    let manifest = generate_manifest(&src, &exclude.unwrap_or_default(), &options);
+    save_hash_cache(&cache);
+    progress.finish();
+
+    let manifest = manifest.map_err(|e| e.to_string())?;
+    fs::write(&output_path, manifest_parser::write(&manifest))
+        .map_err(|e| format!("Failed to write manifest to {}: {}", output_path, e))?;
+    log::info!(
+        "Wrote manifest of {} with {} entries to {}",
+        src.display(),
+        manifest.entries.len(),
+        output_path
+    );
+    Ok(())
+}
+
+/// Lists files and folders left behind in `./game` by older Limbus versions.
+#[tauri::command]
+pub async fn find_orphaned_game_files(
+    app: AppHandle,
+    manifest_path: Option<String>,
+) -> Result<Vec<OrphanEntry>, String> {
+    let game_dir = get_lethe_limbus_folder_location()?;
+    load_manifest(&app, manifest_path)
+        .await?
+        .find_orphans(&game_dir, GAME_FOLDER_ALLOWLIST)
+        .map_err(|e| e.to_string())
+}
+
+/// Deletes the given orphaned entries from `./game`, as confirmed by the user, and returns the
+/// number of bytes freed. Paths that are not orphans (anymore) are left alone.
+#[tauri::command]
+pub async fn delete_orphaned_game_files(
+    app: AppHandle,
+    paths: Vec<String>,
+    manifest_path: Option<String>,
+) -> Result<u64, String> {
+    let game_dir = get_lethe_limbus_folder_location()?;
+    let orphans = load_manifest(&app, manifest_path)
+        .await?
+        .find_orphans(&game_dir, GAME_FOLDER_ALLOWLIST)
+        .map_err(|e| e.to_string())?;
+
+    let mut freed = 0;
+    for path in paths {
+        let Some(orphan) = orphans.iter().find(|orphan| orphan.path == path) else {
+            log::warn!("Not deleting {}: it is not an orphaned file", path);
+            continue;
+        };
+
+        let full_path = game_dir.join(&orphan.path);
+        let result = if orphan.is_dir {
+            fs::remove_dir_all(&full_path)
+        } else {
+            fs::remove_file(&full_path)
+        };
+        result.map_err(|e| format!("Failed to delete {}: {}", full_path.display(), e))?;
+
+        log::info!("Deleted orphaned game file: {}", full_path.display());
+        freed += orphan.size;
+    }
+
+    Ok(freed)
 }
 
 #[tauri::command]
@@ -111,16 +418,46 @@ pub fn get_lethe_limbus_folder_location() -> Result<PathBuf, String> {
 
 pub fn get_lethe_plugins_folder_location() -> Result<PathBuf, String> {
     let dir = get_lethe_limbus_folder_location()?;
-    let plugins_folder = dir.join("bepinex").join("plugins");
+    let plugins_folder = dir.join(PLUGINS_DIR);
     Ok(plugins_folder)
 }
 
+/// Checks whether `./game` matches the current Limbus version. By default only a few files that
+/// change with every update are checked; `sentinels` overrides them and `full` checks every file.
 #[tauri::command]
-pub async fn check_lethe_limbus_up_to_date() -> Result<bool, String> {
+pub async fn check_lethe_limbus_up_to_date(
+    app: AppHandle,
+    manifest_path: Option<String>,
+    sentinels: Option<Vec<String>>,
+    full: Option<bool>,
+    threads: Option<usize>,
+    operation_id: Option<String>,
+) -> Result<InstallCheck, String> {
+    let mode = match (full.unwrap_or(false), sentinels) {
+        (true, _) => InstallCheckMode::Full,
+        (false, Some(sentinels)) => InstallCheckMode::Sentinels(sentinels),
+        (false, None) => InstallCheckMode::default(),
+    };
+
     let lethe_limbus = get_lethe_limbus_folder_location()?;
-    checksum::get_manifest()
-        .await
-        .map_err(|e| e.to_string())?
-        .check_is_up_to_date(&lethe_limbus)
-        .map_err(|e| e.to_string())
+    let cache = load_hash_cache(&app)?;
+    let operation = Operation::start(operation_id)?;
+    let progress = Arc::new(ProgressReporter::new(&app, "check", operation.id()));
+    let options = ManifestOptions::with_threads(threads)
+        .with_cache(cache.clone())
+        .with_progress(progress.clone())
+        .with_cancel(operation.token());
+    let check =
+        load_manifest(&app, manifest_path)
+            .await?
+            .check_install(&lethe_limbus, &mode, &options);
+    save_hash_cache(&cache);
+    progress.finish();
+
+    let check = check.map_err(|e| e.to_string())?;
+    for reason in &check.reasons {
+        log::info!("{}: {}", reason.path, reason.message);
+    }
+    log::info!("Game folder status: {:?}", check.status);
+    Ok(check)
 }
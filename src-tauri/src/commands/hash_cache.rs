@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+pub const HASH_CACHE_FILE: &str = "hash-cache.json";
+
+/// What we knew about a file the last time it was hashed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    sha: String,
+}
+
+impl CacheEntry {
+    fn new(metadata: &Metadata, sha: String) -> Option<CacheEntry> {
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(CacheEntry {
+            size: metadata.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            sha,
+        })
+    }
+
+    fn matches(&self, other: &CacheEntry) -> bool {
+        self.size == other.size
+            && self.mtime_secs == other.mtime_secs
+            && self.mtime_nanos == other.mtime_nanos
+    }
+}
+
+/// Folder root -> path relative to the root -> entry.
+type CacheEntries = HashMap<String, HashMap<String, CacheEntry>>;
+
+/// On-disk cache of file checksums, so files that have not been touched since they were last
+/// hashed do not need to be read again.
+///
+/// An entry is only trusted while the size and modification time of the file are unchanged.
+/// The cache file is purely an optimisation: a missing or unreadable file starts an empty cache.
+#[derive(Debug)]
+pub struct HashCache {
+    path: PathBuf,
+    entries: Mutex<CacheEntries>,
+    dirty: AtomicBool,
+}
+
+fn root_key(root: &Path) -> String {
+    root.canonicalize()
+        .unwrap_or_else(|_| root.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+impl HashCache {
+    /// Loads the cache stored at `path`, or starts an empty one if it cannot be read.
+    pub fn load(path: PathBuf) -> HashCache {
+        let entries = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                log::warn!("Ignoring corrupted hash cache {}: {}", path.display(), e);
+                CacheEntries::new()
+            }),
+            Err(_) => CacheEntries::new(),
+        };
+
+        HashCache {
+            path,
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the cached checksum of `root/name` if the file is unchanged since it was hashed.
+    pub fn get(&self, root: &Path, name: &str, metadata: &Metadata) -> Option<String> {
+        let current = CacheEntry::new(metadata, String::new())?;
+        let entries = self.entries.lock().unwrap();
+        let cached = entries.get(&root_key(root))?.get(name)?;
+        cached.matches(&current).then(|| cached.sha.clone())
+    }
+
+    /// Remembers the checksum of `root/name` for its current size and modification time.
+    pub fn insert(&self, root: &Path, name: &str, metadata: &Metadata, sha: String) {
+        let Some(entry) = CacheEntry::new(metadata, sha) else {
+            return;
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        let folder = entries.entry(root_key(root)).or_default();
+        if folder.get(name) != Some(&entry) {
+            folder.insert(name.to_string(), entry);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Moves the checksums of the folder `from` to the folder `to`, after `from` was renamed.
+    /// `from` must be the canonical path, as it no longer exists.
+    pub fn move_root(&self, from: &Path, to: &Path) {
+        let mut entries = self.entries.lock().unwrap();
+        let moved = entries.remove(&root_key(from)).unwrap_or_default();
+        entries.insert(root_key(to), moved);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Writes the cache back to disk if anything changed since it was loaded.
+    pub fn save(&self) -> std::io::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // write to a temporary file first so a crash never leaves a truncated cache behind
+        let data = serde_json::to_vec(&*self.entries.lock().unwrap())?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_cache_invalidation_and_persistence() {
+        let tmp = TempDir::new("hash-cache");
+        let root = tmp.path().join("game");
+        fs::create_dir_all(&root).unwrap();
+        let file = root.join("GameAssembly.dll");
+        fs::write(&file, b"assembly").unwrap();
+
+        let cache_path = tmp.path().join(HASH_CACHE_FILE);
+        let cache = HashCache::load(cache_path.clone());
+        let metadata = fs::metadata(&file).unwrap();
+        assert_eq!(cache.get(&root, "GameAssembly.dll", &metadata), None);
+
+        cache.insert(&root, "GameAssembly.dll", &metadata, "ABC".to_string());
+        assert_eq!(
+            cache.get(&root, "GameAssembly.dll", &metadata),
+            Some("ABC".to_string())
+        );
+        assert_eq!(cache.get(&root, "Other.dll", &metadata), None);
+        assert_eq!(cache.get(tmp.path(), "GameAssembly.dll", &metadata), None);
+
+        cache.save().unwrap();
+        let reloaded = HashCache::load(cache_path.clone());
+        assert_eq!(
+            reloaded.get(&root, "GameAssembly.dll", &metadata),
+            Some("ABC".to_string())
+        );
+
+        // a new modification time invalidates the entry
+        let handle = File::options().write(true).open(&file).unwrap();
+        handle
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let touched = fs::metadata(&file).unwrap();
+        assert_eq!(reloaded.get(&root, "GameAssembly.dll", &touched), None);
+
+        // and so does a new size
+        fs::write(&file, b"patched assembly").unwrap();
+        let resized = fs::metadata(&file).unwrap();
+        assert_eq!(reloaded.get(&root, "GameAssembly.dll", &resized), None);
+
+        // a deleted or corrupted cache simply starts empty
+        fs::write(&cache_path, b"{ not json").unwrap();
+        assert_eq!(
+            HashCache::load(cache_path.clone()).get(&root, "GameAssembly.dll", &metadata),
+            None
+        );
+        fs::remove_file(&cache_path).unwrap();
+        assert_eq!(
+            HashCache::load(cache_path).get(&root, "GameAssembly.dll", &metadata),
+            None
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Longest time to wait for the next bytes of a response before giving up.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// Attempts per request, including the first one.
+pub const MAX_ATTEMPTS: u32 = 4;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+static CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT)
+        .user_agent(concat!("Zwei/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("failed to build the HTTP client")
+});
+
+/// The client every download goes through, so they all share connections and timeouts.
+pub fn client() -> &'static Client {
+    &CLIENT
+}
+
+/// Why a download failed. Its message is what the UI shows.
+#[derive(Debug)]
+pub enum HttpError {
+    /// The server could not be reached.
+    Connect { url: String, message: String },
+    /// The server stopped responding.
+    Timeout { url: String },
+    /// The server answered with an error status.
+    Status { url: String, status: StatusCode },
+    /// The response broke off or could not be read.
+    Transfer { url: String, message: String },
+}
+
+impl HttpError {
+    /// Whether trying again later may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            HttpError::Connect { .. } | HttpError::Timeout { .. } | HttpError::Transfer { .. } => {
+                true
+            }
+            HttpError::Status { status, .. } => {
+                status.is_server_error()
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+
+    pub fn from_reqwest(url: &str, err: reqwest::Error) -> HttpError {
+        let url = url.to_string();
+        if err.is_timeout() {
+            HttpError::Timeout { url }
+        } else if let Some(status) = err.status() {
+            HttpError::Status { url, status }
+        } else if err.is_connect() {
+            let message = match err.source() {
+                Some(source) => source.to_string(),
+                None => err.to_string(),
+            };
+            HttpError::Connect { url, message }
+        } else {
+            HttpError::Transfer {
+                url,
+                message: err.to_string(),
+            }
+        }
+    }
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::Connect { url, message } => {
+                write!(f, "Could not connect to {}: {}", url, message)
+            }
+            HttpError::Timeout { url } => write!(f, "Timed out waiting for {}", url),
+            HttpError::Status { url, status } => write!(f, "{} returned {}", url, status),
+            HttpError::Transfer { url, message } => {
+                write!(f, "Download of {} was interrupted: {}", url, message)
+            }
+        }
+    }
+}
+
+impl Error for HttpError {}
+
+/// How long to wait before the given retry, doubling with every attempt.
+pub fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF * 2u32.pow(attempt.saturating_sub(1))
+}
+
+/// Sends the request built by `request`, retrying transient failures with exponential backoff.
+/// Error statuses are turned into [`HttpError::Status`]; other statuses such as 304 are returned
+/// as they are.
+pub async fn send(
+    url: &str,
+    request: impl Fn(&Client) -> RequestBuilder,
+) -> Result<Response, HttpError> {
+    let mut attempt = 1;
+    loop {
+        let result = match request(client()).send().await {
+            Ok(response) => response
+                .error_for_status()
+                .map_err(|err| HttpError::from_reqwest(url, err)),
+            Err(err) => Err(HttpError::from_reqwest(url, err)),
+        };
+        match result {
+            Err(err) if err.is_transient() && attempt < MAX_ATTEMPTS => {
+                let delay = backoff(attempt);
+                log::warn!("{}, retrying in {:?}", err, delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transient_errors() {
+        let status = |status| HttpError::Status {
+            url: "https://lethelc.site/Lethe.dll".to_string(),
+            status,
+        };
+        assert!(status(StatusCode::BAD_GATEWAY).is_transient());
+        assert!(status(StatusCode::TOO_MANY_REQUESTS).is_transient());
+        assert!(!status(StatusCode::NOT_FOUND).is_transient());
+        assert!(!status(StatusCode::FORBIDDEN).is_transient());
+        assert_eq!(
+            status(StatusCode::NOT_FOUND).to_string(),
+            "https://lethelc.site/Lethe.dll returned 404 Not Found"
+        );
+
+        assert_eq!(backoff(1), Duration::from_millis(500));
+        assert_eq!(backoff(3), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::commands::checksum::{calculate_checksum, remove_file_if_exists, BoxError};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Something Zwei installs into the game folder on top of Limbus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallComponent {
+    BepInEx,
+    Lethe,
+    Config,
+}
+
+impl InstallComponent {
+    /// Where the manifest of the component is kept, relative to the game folder.
+    fn manifest_path(self) -> &'static str {
+        match self {
+            InstallComponent::BepInEx => bepinex_path!("zwei-bepinex.json"),
+            InstallComponent::Lethe => bepinex_path!("zwei-lethe.json"),
+            InstallComponent::Config => bepinex_path!("zwei-config.json"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstalledFile {
+    /// Relative to the game folder.
+    pub path: String,
+    /// SHA-1 of the file as it was installed, in hex.
+    pub sha1: String,
+}
+
+/// The files a component put into the game folder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallManifest {
+    pub component: InstallComponent,
+    pub version: Option<String>,
+    /// SHA-1 of the zip or DLL the files came from, identifying the exact release.
+    pub artifact_sha1: Option<String>,
+    pub files: Vec<InstalledFile>,
+}
+
+/// What [`InstallManifest::uninstall`] did.
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UninstallReport {
+    pub removed: Vec<String>,
+    /// Files changed since they were installed. They are kept unless removal was forced.
+    pub modified: Vec<String>,
+    /// Files that were already gone.
+    pub missing: Vec<String>,
+}
+
+/// Removes the folders leading to `path` below `game_dir` that became empty.
+pub fn remove_empty_parents(game_dir: &Path, path: &Path) {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current == game_dir || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+impl InstallManifest {
+    /// Describes `files` of `game_dir`, hashing them as they are now.
+    pub fn create(
+        game_dir: &Path,
+        component: InstallComponent,
+        version: Option<String>,
+        artifact_sha1: Option<String>,
+        files: &[String],
+    ) -> Result<InstallManifest, BoxError> {
+        let files = files
+            .iter()
+            .map(|path| {
+                Ok(InstalledFile {
+                    path: path.clone(),
+                    sha1: calculate_checksum(&game_dir.join(path))?,
+                })
+            })
+            .collect::<Result<_, BoxError>>()?;
+        Ok(InstallManifest {
+            component,
+            version,
+            artifact_sha1,
+            files,
+        })
+    }
+
+    /// The manifest of `component` in `game_dir`, if it was installed by Zwei.
+    pub fn load(game_dir: &Path, component: InstallComponent) -> Option<InstallManifest> {
+        let text = fs::read_to_string(game_dir.join(component.manifest_path())).ok()?;
+        match serde_json::from_str(&text) {
+            Ok(manifest) => Some(manifest),
+            Err(err) => {
+                log::warn!(
+                    "Ignoring unreadable {:?} install manifest: {}",
+                    component,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, game_dir: &Path) -> Result<(), BoxError> {
+        let path = game_dir.join(self.component.manifest_path());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Removes the installed files from `game_dir`, along with folders left empty. Files the user
+    /// changed are kept and reported, unless `force` is set. The manifest is then saved listing
+    /// only the kept files, so they can still be removed later, and deleted once none are left.
+    pub fn uninstall(&self, game_dir: &Path, force: bool) -> Result<UninstallReport, BoxError> {
+        let mut report = UninstallReport::default();
+        let mut kept = Vec::new();
+        for file in &self.files {
+            let path = game_dir.join(&file.path);
+            if !path.exists() {
+                report.missing.push(file.path.clone());
+                continue;
+            }
+            let modified = !calculate_checksum(&path)?.eq_ignore_ascii_case(&file.sha1);
+            if modified {
+                report.modified.push(file.path.clone());
+                if !force {
+                    kept.push(file.clone());
+                    continue;
+                }
+            }
+            remove_file_if_exists(&path)?;
+            remove_empty_parents(game_dir, &path);
+            report.removed.push(file.path.clone());
+        }
+        if !kept.is_empty() {
+            InstallManifest {
+                files: kept,
+                ..self.clone()
+            }
+            .save(game_dir)?;
+            return Ok(report);
+        }
+        let manifest = game_dir.join(self.component.manifest_path());
+        remove_file_if_exists(&manifest)?;
+        remove_empty_parents(game_dir, &manifest);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn test_uninstall_keeps_modified_files() {
+        let tmp = TempDir::new("install-manifest");
+        let game = tmp.path();
+        let files = [
+            "winhttp.dll".to_string(),
+            "BepInEx/core/BepInEx.Core.dll".to_string(),
+            "BepInEx/core/BepInEx.Preloader.dll".to_string(),
+            "BepInEx/config/BepInEx.cfg".to_string(),
+        ];
+        for file in &files {
+            fs::create_dir_all(game.join(file).parent().unwrap()).unwrap();
+            fs::write(game.join(file), file).unwrap();
+        }
+        fs::create_dir_all(game.join("BepInEx/plugins")).unwrap();
+        fs::write(game.join("BepInEx/plugins/Other.dll"), b"other").unwrap();
+
+        let manifest = InstallManifest::create(
+            game,
+            InstallComponent::BepInEx,
+            Some("6.0.0-be.733".to_string()),
+            None,
+            &files,
+        )
+        .unwrap();
+        manifest.save(game).unwrap();
+        assert_eq!(
+            InstallManifest::load(game, InstallComponent::BepInEx),
+            Some(manifest.clone())
+        );
+        assert_eq!(InstallManifest::load(game, InstallComponent::Lethe), None);
+
+        fs::write(game.join("BepInEx/config/BepInEx.cfg"), b"tweaked").unwrap();
+        fs::remove_file(game.join("BepInEx/core/BepInEx.Preloader.dll")).unwrap();
+        let report = manifest.uninstall(game, false).unwrap();
+        assert_eq!(
+            report,
+            UninstallReport {
+                removed: vec![
+                    "winhttp.dll".to_string(),
+                    "BepInEx/core/BepInEx.Core.dll".to_string()
+                ],
+                modified: vec!["BepInEx/config/BepInEx.cfg".to_string()],
+                missing: vec!["BepInEx/core/BepInEx.Preloader.dll".to_string()],
+            }
+        );
+        assert!(!game.join("BepInEx/core").exists());
+        assert!(game.join("BepInEx/config/BepInEx.cfg").exists());
+        assert!(game.join("BepInEx/plugins/Other.dll").exists());
+
+        // the kept file is still recorded, so it can be removed later
+        let kept = InstallManifest::load(game, InstallComponent::BepInEx).unwrap();
+        assert_eq!(kept.version, manifest.version);
+        assert_eq!(kept.files, [manifest.files[3].clone()]);
+        let report = kept.uninstall(game, false).unwrap();
+        assert_eq!(report.modified, ["BepInEx/config/BepInEx.cfg"]);
+        assert!(InstallManifest::load(game, InstallComponent::BepInEx).is_some());
+
+        // forcing it removes modified files as well, and the manifest with them
+        let report = kept.uninstall(game, true).unwrap();
+        assert_eq!(report.removed, ["BepInEx/config/BepInEx.cfg"]);
+        assert!(!game.join("BepInEx/config").exists());
+        assert_eq!(InstallManifest::load(game, InstallComponent::BepInEx), None);
+    }
+}
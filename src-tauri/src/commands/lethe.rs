@@ -0,0 +1,361 @@
+use crate::commands::checksum::{calculate_checksum, remove_file_if_exists, BoxError};
+use crate::commands::install_manifest::{InstallComponent, InstallManifest};
+use crate::commands::release::verify_file;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many replaced Lethe versions are kept to roll back to.
+pub const KEEP_LETHE_VERSIONS: usize = 3;
+
+/// Relative to the game folder.
+pub const LETHE_DLL: &str = bepinex_path!("plugins/Lethe.dll");
+/// Lists the kept versions, newest first, relative to the history folder.
+const HISTORY_FILE: &str = "history.json";
+
+/// Version reported for a Lethe.dll Zwei did not install itself.
+pub const UNKNOWN_VERSION: &str = "unknown";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LetheVersion {
+    pub version: String,
+    /// SHA-1 of the DLL, in hex.
+    pub sha1: String,
+}
+
+/// The Lethe version installed in `game_dir`. The recorded version is only trusted while the DLL
+/// still has the recorded hash.
+pub fn installed_lethe(game_dir: &Path) -> Result<Option<LetheVersion>, BoxError> {
+    let dll = game_dir.join(LETHE_DLL);
+    if !dll.exists() {
+        return Ok(None);
+    }
+    let sha1 = calculate_checksum(&dll)?;
+    let version = InstallManifest::load(game_dir, InstallComponent::Lethe)
+        .filter(|recorded| {
+            recorded
+                .artifact_sha1
+                .as_ref()
+                .is_some_and(|recorded| recorded.eq_ignore_ascii_case(&sha1))
+        })
+        .and_then(|recorded| recorded.version);
+    Ok(Some(LetheVersion {
+        version: version.unwrap_or_else(|| UNKNOWN_VERSION.to_string()),
+        sha1,
+    }))
+}
+
+fn record_lethe(game_dir: &Path, installed: &LetheVersion) -> Result<(), BoxError> {
+    InstallManifest::create(
+        game_dir,
+        InstallComponent::Lethe,
+        Some(installed.version.clone()),
+        Some(installed.sha1.clone()),
+        &[LETHE_DLL.to_string()],
+    )?
+    .save(game_dir)
+}
+
+/// Which of the `known` versions the DLL at `dll` is, going by its hash. A DLL that is none of
+/// them is rejected, so a file of unknown origin is never installed as a release.
+pub fn identify_lethe(dll: &Path, known: &[LetheVersion]) -> Result<LetheVersion, BoxError> {
+    let sha1 = calculate_checksum(dll)?;
+    known
+        .iter()
+        .find(|version| version.sha1.eq_ignore_ascii_case(&sha1))
+        .cloned()
+        .ok_or_else(|| {
+            format!(
+                "{} is not a known Lethe release (SHA-1 {})",
+                dll.display(),
+                sha1
+            )
+            .into()
+        })
+}
+
+/// Installs the DLL at `dll` into `game_dir`. It has to be `version`, which is checked before
+/// anything is replaced. The replaced Lethe is kept in `history`.
+pub fn install_lethe(
+    game_dir: &Path,
+    history: &LetheHistory,
+    dll: &Path,
+    version: &LetheVersion,
+) -> Result<(), BoxError> {
+    verify_file(dll, "Lethe.dll", &version.sha1)?;
+    history.archive_installed(game_dir)?;
+    let destination = game_dir.join(LETHE_DLL);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(dll, destination)?;
+    record_lethe(game_dir, version)
+}
+
+/// Previous Lethe versions, kept in their own folder outside the game so BepInEx does not load
+/// them.
+pub struct LetheHistory {
+    dir: PathBuf,
+}
+
+impl LetheHistory {
+    pub fn new(dir: PathBuf) -> LetheHistory {
+        LetheHistory { dir }
+    }
+
+    /// The kept versions, newest first.
+    pub fn versions(&self) -> Vec<LetheVersion> {
+        fs::read_to_string(self.dir.join(HISTORY_FILE))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn dll_path(&self, version: &LetheVersion) -> PathBuf {
+        self.dir
+            .join(format!("{}.dll", version.sha1.to_uppercase()))
+    }
+
+    /// Stores `versions`, deleting the DLLs of those beyond [`KEEP_LETHE_VERSIONS`].
+    fn save(&self, mut versions: Vec<LetheVersion>) -> Result<(), BoxError> {
+        for dropped in versions.split_off(versions.len().min(KEEP_LETHE_VERSIONS)) {
+            if !versions.iter().any(|kept| kept.sha1 == dropped.sha1) {
+                remove_file_if_exists(&self.dll_path(&dropped))?;
+            }
+        }
+        fs::write(
+            self.dir.join(HISTORY_FILE),
+            serde_json::to_vec_pretty(&versions)?,
+        )?;
+        Ok(())
+    }
+
+    /// Copies the DLL of `installed` from `game_dir` into the history, without listing it yet.
+    fn copy_installed(&self, game_dir: &Path, installed: &LetheVersion) -> Result<(), BoxError> {
+        fs::create_dir_all(&self.dir)?;
+        fs::copy(game_dir.join(LETHE_DLL), self.dll_path(installed))?;
+        Ok(())
+    }
+
+    /// Keeps a copy of the Lethe installed in `game_dir`, if any, as the newest previous version.
+    pub fn archive_installed(&self, game_dir: &Path) -> Result<(), BoxError> {
+        let Some(installed) = installed_lethe(game_dir)? else {
+            return Ok(());
+        };
+        self.copy_installed(game_dir, &installed)?;
+        self.save(with_newest(self.versions(), installed))
+    }
+
+    /// Puts the kept `version` (the newest one if not given) back into `game_dir`. The replaced
+    /// Lethe is kept in its place.
+    pub fn roll_back(
+        &self,
+        game_dir: &Path,
+        version: Option<&str>,
+    ) -> Result<LetheVersion, BoxError> {
+        let mut versions = self.versions();
+        let index = match version {
+            Some(version) => versions.iter().position(|kept| kept.version == version),
+            None => (!versions.is_empty()).then_some(0),
+        }
+        .ok_or_else(|| match version {
+            Some(version) => format!("Lethe {} is not kept to roll back to", version),
+            None => "There is no previous Lethe version to roll back to".to_string(),
+        })?;
+        let target = versions.remove(index);
+
+        // the history is only changed once the DLL is in place, so a failure loses nothing
+        let dll = game_dir.join(LETHE_DLL);
+        let restored = dll.with_extension("dll.rollback");
+        let installed = installed_lethe(game_dir)?;
+        let swapped = self.swap_in(game_dir, &target, installed.as_ref(), &restored);
+        if swapped.is_err() {
+            if let Err(err) = remove_file_if_exists(&restored) {
+                log::warn!("Failed to remove {}: {}", restored.display(), err);
+            }
+        }
+        swapped?;
+
+        if let Some(installed) = installed {
+            versions = with_newest(versions, installed);
+        }
+        if !versions
+            .iter()
+            .any(|kept| kept.sha1.eq_ignore_ascii_case(&target.sha1))
+        {
+            remove_file_if_exists(&self.dll_path(&target))?;
+        }
+        self.save(versions)?;
+        record_lethe(game_dir, &target)?;
+        Ok(target)
+    }
+
+    /// Moves the kept `target` over the Lethe in `game_dir` by way of `restored`, copying
+    /// `installed` into the history first.
+    fn swap_in(
+        &self,
+        game_dir: &Path,
+        target: &LetheVersion,
+        installed: Option<&LetheVersion>,
+        restored: &Path,
+    ) -> Result<(), BoxError> {
+        if let Some(parent) = restored.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(self.dll_path(target), restored)?;
+        if let Some(installed) = installed {
+            self.copy_installed(game_dir, installed)?;
+        }
+        fs::rename(restored, game_dir.join(LETHE_DLL))?;
+        Ok(())
+    }
+}
+
+/// `versions` with `newest` moved or added to the front.
+fn with_newest(mut versions: Vec<LetheVersion>, newest: LetheVersion) -> Vec<LetheVersion> {
+    versions.retain(|kept| !kept.sha1.eq_ignore_ascii_case(&newest.sha1));
+    versions.insert(0, newest);
+    versions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn install(game: &Path, version: &str) -> LetheVersion {
+        let dll = game.join(LETHE_DLL);
+        fs::create_dir_all(dll.parent().unwrap()).unwrap();
+        fs::write(&dll, format!("lethe {}", version)).unwrap();
+        let installed = LetheVersion {
+            version: version.to_string(),
+            sha1: calculate_checksum(&dll).unwrap(),
+        };
+        record_lethe(game, &installed).unwrap();
+        installed
+    }
+
+    fn read_dll(game: &Path) -> String {
+        fs::read_to_string(game.join(LETHE_DLL)).unwrap()
+    }
+
+    #[test]
+    fn test_installed_version() {
+        let tmp = TempDir::new("lethe-installed");
+        let game = tmp.path();
+        assert_eq!(installed_lethe(game).unwrap(), None);
+
+        let installed = install(game, "1.0.0");
+        assert_eq!(installed_lethe(game).unwrap(), Some(installed));
+
+        // a DLL replaced by hand no longer matches the record
+        fs::write(game.join(LETHE_DLL), b"custom build").unwrap();
+        assert_eq!(
+            installed_lethe(game).unwrap().unwrap().version,
+            UNKNOWN_VERSION
+        );
+    }
+
+    #[test]
+    fn test_install_checks_dll() {
+        let tmp = TempDir::new("lethe-install");
+        let game = tmp.path().join("game");
+        let history = LetheHistory::new(tmp.path().join("history"));
+        let dll = tmp.path().join("Lethe.dll");
+        fs::write(&dll, b"hello world").unwrap();
+        let version = LetheVersion {
+            version: "1.0.0".to_string(),
+            sha1: "2AAE6C35C94FCFB415DBE95F408B9CE91EE846ED".to_string(),
+        };
+
+        fs::write(&dll, b"hello world!").unwrap();
+        assert!(install_lethe(&game, &history, &dll, &version).is_err());
+        assert!(!game.join(LETHE_DLL).exists());
+
+        fs::write(&dll, b"hello world").unwrap();
+        install_lethe(&game, &history, &dll, &version).unwrap();
+        assert_eq!(read_dll(&game), "hello world");
+        assert_eq!(installed_lethe(&game).unwrap(), Some(version));
+    }
+
+    #[test]
+    fn test_identify_dll() {
+        let tmp = TempDir::new("lethe-identify");
+        let dll = tmp.path().join("Lethe.dll");
+        fs::write(&dll, b"hello world").unwrap();
+        let known = [
+            LetheVersion {
+                version: "1.0.0".to_string(),
+                sha1: "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+            },
+            LetheVersion {
+                version: "1.1.0".to_string(),
+                sha1: "2AAE6C35C94FCFB415DBE95F408B9CE91EE846ED".to_string(),
+            },
+        ];
+        assert_eq!(identify_lethe(&dll, &known).unwrap(), known[1]);
+
+        fs::write(&dll, b"hello world!").unwrap();
+        assert!(identify_lethe(&dll, &known).is_err());
+        assert!(identify_lethe(&dll, &[]).is_err());
+    }
+
+    #[test]
+    fn test_keep_and_roll_back_versions() {
+        let tmp = TempDir::new("lethe-history");
+        let game = tmp.path().join("game");
+        let history = LetheHistory::new(tmp.path().join("history"));
+        assert!(history.roll_back(&game, None).is_err());
+
+        for version in ["1.0.0", "1.1.0", "1.2.0", "1.3.0", "1.4.0"] {
+            history.archive_installed(&game).unwrap();
+            install(&game, version);
+        }
+        let kept: Vec<String> = history
+            .versions()
+            .into_iter()
+            .map(|kept| kept.version)
+            .collect();
+        assert_eq!(kept, ["1.3.0", "1.2.0", "1.1.0"]);
+        assert_eq!(fs::read_dir(tmp.path().join("history")).unwrap().count(), 4);
+
+        let restored = history.roll_back(&game, None).unwrap();
+        assert_eq!(restored.version, "1.3.0");
+        assert_eq!(read_dll(&game), "lethe 1.3.0");
+        assert_eq!(installed_lethe(&game).unwrap(), Some(restored));
+
+        // the replaced version can be rolled back to in turn
+        history.roll_back(&game, Some("1.1.0")).unwrap();
+        assert_eq!(read_dll(&game), "lethe 1.1.0");
+        let kept: Vec<String> = history
+            .versions()
+            .into_iter()
+            .map(|kept| kept.version)
+            .collect();
+        assert_eq!(kept, ["1.3.0", "1.4.0", "1.2.0"]);
+        assert!(history.roll_back(&game, Some("1.0.0")).is_err());
+        assert!(!game.join(LETHE_DLL).with_extension("dll.rollback").exists());
+    }
+
+    #[test]
+    fn test_failed_roll_back_keeps_history() {
+        let tmp = TempDir::new("lethe-history-failed");
+        let game = tmp.path().join("game");
+        let history = LetheHistory::new(tmp.path().join("history"));
+        install(&game, "1.0.0");
+        history.archive_installed(&game).unwrap();
+        let kept = install(&game, "1.1.0");
+        history.archive_installed(&game).unwrap();
+        let installed = install(&game, "1.2.0");
+        let versions = history.versions();
+
+        // the installed DLL cannot be kept while a folder is in the way
+        fs::create_dir_all(history.dll_path(&installed).join("in-the-way")).unwrap();
+        assert!(history.roll_back(&game, Some("1.1.0")).is_err());
+        assert_eq!(history.versions(), versions);
+        assert!(history.dll_path(&kept).is_file());
+        assert_eq!(read_dll(&game), "lethe 1.2.0");
+        assert!(!game.join(LETHE_DLL).with_extension("dll.rollback").exists());
+    }
+}
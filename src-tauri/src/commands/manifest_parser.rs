@@ -0,0 +1,432 @@
+//! Parser for the SteamCMD/DepotDownloader style `limbus-manifest.txt`:
+//!
+//! ```text
+//! Content Manifest for Depot 1973531
+//!
+//! Manifest ID / date     : 1234567890123456789 / 12/10/2024 02:11:39
+//! Total number of files  : 2
+//! Total number of chunks : 1
+//! Total bytes on disk    : 1024
+//! Total bytes compressed : 512
+//!
+//!           Size Chunks File SHA                                 Flags Name
+//!           1024      1 5F1B0A6C1B4B4F8A0C3E0E39A1B2C3D4E5F60718     0 GameAssembly.dll
+//!              0      0 0000000000000000000000000000000000000000    40 LimbusCompany_Data
+//! ```
+use regex::Regex;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Write};
+
+/// Steam depot file flags, as printed (in hex) in the `Flags` column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileFlags(pub u32);
+
+impl FileFlags {
+    pub const DIRECTORY: u32 = 0x40;
+
+    pub fn contains(&self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.contains(Self::DIRECTORY)
+    }
+}
+
+/// The `key : value` fields above the file table. Fields missing from the file are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManifestHeader {
+    pub depot_id: Option<u64>,
+    pub manifest_id: Option<u64>,
+    pub date: Option<String>,
+    pub total_files: Option<u64>,
+    pub total_chunks: Option<u64>,
+    pub total_bytes: Option<u64>,
+    pub total_bytes_compressed: Option<u64>,
+}
+
+/// One row of the file table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub size: u64,
+    pub chunks: u64,
+    pub sha: String,
+    pub flags: FileFlags,
+    /// Path relative to the game folder, always using `/` as separator.
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedManifest {
+    pub header: ManifestHeader,
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    MissingTableHeader,
+    MissingColumn(&'static str),
+    InvalidNumber { field: &'static str, value: String },
+    InvalidSha(String),
+    InvalidFlags(String),
+    DuplicateFile(String),
+}
+
+/// Error raised while parsing a manifest, with the 1-based line it was found on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid manifest at line {}: ", self.line)?;
+        match &self.kind {
+            ParseErrorKind::MissingTableHeader => write!(f, "file table header not found"),
+            ParseErrorKind::MissingColumn(column) => write!(f, "missing {} column", column),
+            ParseErrorKind::InvalidNumber { field, value } => {
+                write!(f, "{} '{}' is not a valid number", field, value)
+            }
+            ParseErrorKind::InvalidSha(sha) => write!(f, "'{}' is not a SHA-1 hash", sha),
+            ParseErrorKind::InvalidFlags(flags) => write!(f, "'{}' are not valid flags", flags),
+            ParseErrorKind::DuplicateFile(name) => write!(f, "{} is listed twice", name),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+fn parse_number(line: usize, field: &'static str, value: &str) -> Result<u64, ParseError> {
+    value.parse().map_err(|_| ParseError {
+        line,
+        kind: ParseErrorKind::InvalidNumber {
+            field,
+            value: value.to_string(),
+        },
+    })
+}
+
+/// Splits off the next whitespace separated column of `rest`.
+fn next_column<'a>(
+    line: usize,
+    column: &'static str,
+    rest: &mut &'a str,
+) -> Result<&'a str, ParseError> {
+    let trimmed = rest.trim_start();
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    if end == 0 {
+        return Err(ParseError {
+            line,
+            kind: ParseErrorKind::MissingColumn(column),
+        });
+    }
+    *rest = &trimmed[end..];
+    Ok(&trimmed[..end])
+}
+
+fn parse_entry(line: usize, text: &str) -> Result<ManifestEntry, ParseError> {
+    let mut rest = text;
+    let size = parse_number(line, "Size", next_column(line, "Size", &mut rest)?)?;
+    let chunks = parse_number(line, "Chunks", next_column(line, "Chunks", &mut rest)?)?;
+
+    let sha = next_column(line, "File SHA", &mut rest)?;
+    if sha.len() != 40 || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ParseError {
+            line,
+            kind: ParseErrorKind::InvalidSha(sha.to_string()),
+        });
+    }
+
+    let flags = next_column(line, "Flags", &mut rest)?;
+    let flags = u32::from_str_radix(flags, 16).map_err(|_| ParseError {
+        line,
+        kind: ParseErrorKind::InvalidFlags(flags.to_string()),
+    })?;
+
+    // names may contain spaces, so the name is everything after the flags
+    let name = rest.trim();
+    if name.is_empty() {
+        return Err(ParseError {
+            line,
+            kind: ParseErrorKind::MissingColumn("Name"),
+        });
+    }
+
+    Ok(ManifestEntry {
+        size,
+        chunks,
+        sha: sha.to_uppercase(),
+        flags: FileFlags(flags),
+        name: name.replace('\\', "/"),
+    })
+}
+
+fn parse_header_field(
+    header: &mut ManifestHeader,
+    line: usize,
+    text: &str,
+) -> Result<(), ParseError> {
+    if let Some(depot) = text.trim().strip_prefix("Content Manifest for Depot") {
+        header.depot_id = Some(parse_number(line, "Depot", depot.trim())?);
+        return Ok(());
+    }
+
+    let Some((key, value)) = text.split_once(':') else {
+        return Ok(());
+    };
+    let value = value.trim();
+    match key.trim() {
+        "Manifest ID / date" => {
+            let (id, date) = value.split_once('/').unwrap_or((value, ""));
+            header.manifest_id = Some(parse_number(line, "Manifest ID", id.trim())?);
+            header.date = Some(date.trim().to_string()).filter(|date| !date.is_empty());
+        }
+        "Total number of files" => {
+            header.total_files = Some(parse_number(line, "Total number of files", value)?)
+        }
+        "Total number of chunks" => {
+            header.total_chunks = Some(parse_number(line, "Total number of chunks", value)?)
+        }
+        "Total bytes on disk" => {
+            header.total_bytes = Some(parse_number(line, "Total bytes on disk", value)?)
+        }
+        "Total bytes compressed" => {
+            header.total_bytes_compressed =
+                Some(parse_number(line, "Total bytes compressed", value)?)
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Parses the content of a manifest file.
+pub fn parse(text: &str) -> Result<ParsedManifest, ParseError> {
+    let table_header = Regex::new(r"^\s*Size\s+Chunks\s+File SHA\s+Flags\s+Name\s*$").unwrap();
+
+    let mut manifest = ParsedManifest::default();
+    let mut names = HashSet::new();
+    let mut in_table = false;
+    let mut last_line = 0;
+
+    for (index, text) in text.lines().enumerate() {
+        let line = index + 1;
+        last_line = line;
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        if !in_table {
+            if table_header.is_match(text) {
+                in_table = true;
+            } else {
+                parse_header_field(&mut manifest.header, line, text)?;
+            }
+            continue;
+        }
+
+        let entry = parse_entry(line, text)?;
+        if !names.insert(entry.name.clone()) {
+            return Err(ParseError {
+                line,
+                kind: ParseErrorKind::DuplicateFile(entry.name),
+            });
+        }
+        manifest.entries.push(entry);
+    }
+
+    if !in_table {
+        return Err(ParseError {
+            line: last_line,
+            kind: ParseErrorKind::MissingTableHeader,
+        });
+    }
+
+    Ok(manifest)
+}
+
+/// Formats `manifest` the way SteamCMD does, so that [`parse`] reads it back unchanged.
+pub fn write(manifest: &ParsedManifest) -> String {
+    let header = &manifest.header;
+    let mut text = String::new();
+    if let Some(depot_id) = header.depot_id {
+        writeln!(text, "Content Manifest for Depot {}\n", depot_id).unwrap();
+    }
+    if let Some(manifest_id) = header.manifest_id {
+        let date = header.date.as_deref().unwrap_or_default();
+        writeln!(text, "Manifest ID / date     : {} / {}", manifest_id, date).unwrap();
+    }
+    let totals = [
+        ("Total number of files  ", header.total_files),
+        ("Total number of chunks ", header.total_chunks),
+        ("Total bytes on disk    ", header.total_bytes),
+        ("Total bytes compressed ", header.total_bytes_compressed),
+    ];
+    for (key, value) in totals {
+        if let Some(value) = value {
+            writeln!(text, "{}: {}", key, value).unwrap();
+        }
+    }
+
+    text.push_str(
+        "\n\n          Size Chunks File SHA                                 Flags Name\n",
+    );
+    for entry in &manifest.entries {
+        writeln!(
+            text,
+            "{:>14} {:>6} {} {:>5x} {}",
+            entry.size, entry.chunks, entry.sha, entry.flags.0, entry.name
+        )
+        .unwrap();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+
+    fn error_of(text: &str) -> ParseError {
+        parse(text).expect_err("manifest should not parse")
+    }
+
+    #[test]
+    fn test_parse_fixture() {
+        let manifest = parse(&fixture("limbus-manifest.txt")).unwrap();
+        assert_eq!(
+            manifest.header,
+            ManifestHeader {
+                depot_id: Some(1973531),
+                manifest_id: Some(6048423372834929573),
+                date: Some("12/19/2024 04:33:21".to_string()),
+                total_files: Some(6),
+                total_chunks: Some(5),
+                total_bytes: Some(112993158),
+                total_bytes_compressed: Some(41220154),
+            }
+        );
+        assert_eq!(manifest.entries.len(), 6);
+
+        let exe = &manifest.entries[0];
+        assert_eq!(exe.name, "LimbusCompany.exe");
+        assert_eq!(exe.size, 667648);
+        assert_eq!(exe.chunks, 1);
+        assert_eq!(exe.sha, "8A3C07A7CE5B1A1C1A7E2F2D1E3D4C5B6A798812");
+        assert_eq!(exe.flags, FileFlags(0x20));
+
+        let data = manifest
+            .entries
+            .iter()
+            .find(|entry| entry.name == "LimbusCompany_Data")
+            .unwrap();
+        assert!(data.flags.is_directory());
+        assert_eq!(data.size, 0);
+
+        let catalog = manifest
+            .entries
+            .iter()
+            .find(|entry| entry.name.ends_with("catalog.json"))
+            .unwrap();
+        assert_eq!(
+            catalog.name,
+            "LimbusCompany_Data/StreamingAssets/aa/catalog.json"
+        );
+    }
+
+    #[test]
+    fn test_parse_unusual_layout() {
+        // wider columns, lowercase hashes, backslashes and non-ASCII names with spaces
+        let manifest = parse(&fixture("limbus-manifest-wide.txt")).unwrap();
+        assert_eq!(manifest.header.depot_id, Some(1973531));
+        assert_eq!(manifest.header.date, None);
+        let names: Vec<&str> = manifest.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "LimbusCompany_Data/StreamingAssets/Ëxtra Assets/ダンテ.bundle",
+                "LimbusCompany_Data/StreamingAssets/Ëxtra Assets",
+                "GameAssembly.dll",
+            ]
+        );
+        assert_eq!(manifest.entries[0].size, 123456789012);
+        assert_eq!(
+            manifest.entries[2].sha,
+            "0123456789ABCDEF0123456789ABCDEF01234567"
+        );
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        for name in ["limbus-manifest.txt", "limbus-manifest-wide.txt"] {
+            let manifest = parse(&fixture(name)).unwrap();
+            assert_eq!(parse(&write(&manifest)).unwrap(), manifest, "{}", name);
+        }
+
+        let original = fixture("limbus-manifest.txt");
+        let written = write(&parse(&original).unwrap());
+        let table = |text: &str| -> Vec<String> {
+            let start = text.find("          Size").unwrap();
+            text[start..]
+                .lines()
+                .map(|line| line.trim_end().to_string())
+                .collect()
+        };
+        assert_eq!(table(&written), table(&original));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            error_of(&fixture("limbus-manifest-broken.txt")),
+            ParseError {
+                line: 11,
+                kind: ParseErrorKind::InvalidSha("5F1B0A6C1B4B4F8A0C3E".to_string()),
+            }
+        );
+        assert_eq!(
+            error_of("Content Manifest for Depot 1\n\nno table here\n"),
+            ParseError {
+                line: 3,
+                kind: ParseErrorKind::MissingTableHeader,
+            }
+        );
+
+        let table = "          Size Chunks File SHA                                 Flags Name\n";
+        let sha = "0000000000000000000000000000000000000000";
+        assert_eq!(
+            error_of(&format!("{}        12a      1 {}     0 a.txt", table, sha)).kind,
+            ParseErrorKind::InvalidNumber {
+                field: "Size",
+                value: "12a".to_string()
+            }
+        );
+        assert_eq!(
+            error_of(&format!("{}         12      1 {}    zz a.txt", table, sha)).kind,
+            ParseErrorKind::InvalidFlags("zz".to_string())
+        );
+        assert_eq!(
+            error_of(&format!("{}         12      1 {}     0", table, sha)).kind,
+            ParseErrorKind::MissingColumn("Name")
+        );
+        assert_eq!(
+            error_of(&format!("{}         12      1", table)).kind,
+            ParseErrorKind::MissingColumn("File SHA")
+        );
+        assert_eq!(
+            error_of(&format!(
+                "{0}         12      1 {1}     0 a.txt\n\n          12      1 {1}     0 a.txt",
+                table, sha
+            )),
+            ParseError {
+                line: 4,
+                kind: ParseErrorKind::DuplicateFile("a.txt".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use crate::commands::checksum::{BoxError, VersionManifest};
+use crate::commands::http::{self, HttpError};
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MANIFEST_URL: &str = "https://api.lethelc.site/limbus-manifest.txt";
+
+/// Last manifest that was successfully downloaded, kept for offline use.
+const MANIFEST_CACHE_FILE: &str = "limbus-manifest.txt";
+const MANIFEST_META_FILE: &str = "limbus-manifest.json";
+/// The manifest `./game` was last cloned or synced from, to tell what a newer one changes.
+const INSTALLED_MANIFEST_FILE: &str = "installed-manifest.txt";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedManifestMeta {
+    /// Seconds since the unix epoch.
+    fetched_at: u64,
+    etag: Option<String>,
+}
+
+/// A manifest together with a warning for the user if it did not come from the network.
+pub struct LoadedManifest {
+    pub manifest: VersionManifest,
+    pub warning: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn describe_age(fetched_at: u64) -> String {
+    let age = now().saturating_sub(fetched_at);
+    match age {
+        0..=119 => "just now".to_string(),
+        120..=7199 => format!("{} minutes ago", age / 60),
+        7200..=172799 => format!("{} hours ago", age / 3600),
+        _ => format!("{} days ago", age / 86400),
+    }
+}
+
+fn read_meta(cache_dir: &Path) -> Option<CachedManifestMeta> {
+    let data = fs::read(cache_dir.join(MANIFEST_META_FILE)).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+fn write_cache(cache_dir: &Path, text: &str, meta: &CachedManifestMeta) -> Result<(), BoxError> {
+    fs::create_dir_all(cache_dir)?;
+    let tmp = cache_dir.join(format!("{}.tmp", MANIFEST_CACHE_FILE));
+    fs::write(&tmp, text)?;
+    fs::rename(&tmp, cache_dir.join(MANIFEST_CACHE_FILE))?;
+    fs::write(
+        cache_dir.join(MANIFEST_META_FILE),
+        serde_json::to_vec(meta)?,
+    )?;
+    Ok(())
+}
+
+/// The manifest that was last downloaded to `cache_dir`.
+pub fn cached_manifest(cache_dir: &Path) -> Result<VersionManifest, BoxError> {
+    VersionManifest::from_file(&cache_dir.join(MANIFEST_CACHE_FILE))
+}
+
+/// The manifest `./game` was last cloned or synced from, as recorded by
+/// [`save_installed_manifest`].
+pub fn installed_manifest(cache_dir: &Path) -> Result<VersionManifest, BoxError> {
+    VersionManifest::from_file(&cache_dir.join(INSTALLED_MANIFEST_FILE))
+}
+
+/// Records that `./game` now matches `manifest`.
+pub fn save_installed_manifest(
+    cache_dir: &Path,
+    manifest: &VersionManifest,
+) -> Result<(), BoxError> {
+    fs::create_dir_all(cache_dir)?;
+    let tmp = cache_dir.join(format!("{}.tmp", INSTALLED_MANIFEST_FILE));
+    manifest.save(&tmp)?;
+    fs::rename(&tmp, cache_dir.join(INSTALLED_MANIFEST_FILE))?;
+    Ok(())
+}
+
+/// Downloads the manifest from `url` and stores it in `cache_dir`. If the cached copy is still
+/// current according to its ETag, the server does not need to send it again.
+pub async fn fetch_manifest(url: &str, cache_dir: &Path) -> Result<VersionManifest, BoxError> {
+    let cached_text = fs::read_to_string(cache_dir.join(MANIFEST_CACHE_FILE)).ok();
+    let cached_etag = read_meta(cache_dir).and_then(|meta| meta.etag);
+
+    let response = http::send(url, |client| {
+        let request = client.get(url);
+        match (&cached_text, &cached_etag) {
+            (Some(_), Some(etag)) => request.header(IF_NONE_MATCH, etag),
+            _ => request,
+        }
+    })
+    .await?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(text) = cached_text {
+            let manifest = VersionManifest::parse(&text)?;
+            let meta = CachedManifestMeta {
+                fetched_at: now(),
+                etag: cached_etag,
+            };
+            write_cache(cache_dir, &text, &meta)?;
+            return Ok(manifest);
+        }
+    }
+
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    let text = response
+        .text()
+        .await
+        .map_err(|err| HttpError::from_reqwest(url, err))?;
+
+    // only replace the cached copy with a manifest we know is valid
+    let manifest = VersionManifest::parse(&text)?;
+    let meta = CachedManifestMeta {
+        fetched_at: now(),
+        etag,
+    };
+    if let Err(err) = write_cache(cache_dir, &text, &meta) {
+        log::warn!("Failed to cache the Limbus manifest: {}", err);
+    }
+    Ok(manifest)
+}
+
+/// Downloads the manifest from `url`, falling back to the copy cached in `cache_dir` when the
+/// download fails.
+pub async fn load_manifest(url: &str, cache_dir: &Path) -> Result<LoadedManifest, BoxError> {
+    let err = match fetch_manifest(url, cache_dir).await {
+        Ok(manifest) => {
+            return Ok(LoadedManifest {
+                manifest,
+                warning: None,
+            })
+        }
+        Err(err) => err,
+    };
+
+    log::warn!("Failed to download the Limbus manifest: {}", err);
+    let Ok(manifest) = cached_manifest(cache_dir) else {
+        return Err(format!("Failed to download the Limbus manifest: {}", err).into());
+    };
+
+    let age = read_meta(cache_dir)
+        .map(|meta| describe_age(meta.fetched_at))
+        .unwrap_or_else(|| "at an unknown time".to_string());
+    Ok(LoadedManifest {
+        manifest,
+        warning: Some(format!(
+            "Could not download the Limbus manifest ({}). Using the copy downloaded {}, which may be outdated.",
+            err, age
+        )),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::checksum::{ManifestError, ManifestOptions};
+    use crate::test_utils::TempDir;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Nothing listens on the discard port, so requests fail right away.
+    const OFFLINE_URL: &str = "http://127.0.0.1:9/limbus-manifest.txt";
+
+    fn fixture() -> String {
+        let path = format!(
+            "{}/tests/fixtures/limbus-manifest.txt",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_offline_without_cache_fails() {
+        let tmp = TempDir::new("manifest-offline");
+        assert!(load_manifest(OFFLINE_URL, tmp.path()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_offline_falls_back_to_cache() {
+        let tmp = TempDir::new("manifest-cache");
+        let meta = CachedManifestMeta {
+            fetched_at: now() - 3 * 3600,
+            etag: Some("\"abc\"".to_string()),
+        };
+        write_cache(tmp.path(), &fixture(), &meta).unwrap();
+
+        let loaded = load_manifest(OFFLINE_URL, tmp.path()).await.unwrap();
+        let warning = loaded.warning.expect("cached manifest should warn");
+        assert!(warning.contains("3 hours ago"), "{}", warning);
+        let error = |name| {
+            loaded
+                .manifest
+                .check_file(tmp.path(), name, &ManifestOptions::default())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("GameAssembly.dll"),
+            ManifestError::FileDoesNotExist.to_string()
+        );
+        assert_eq!(error("missing.dll"), ManifestError::UnknownFile.to_string());
+    }
+
+    /// Serves `body` with the ETag `"v1"`, answering 304 Not Modified to requests that already
+    /// have it. Records the If-None-Match header of every request.
+    fn serve(body: String) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/limbus-manifest.txt",
+            listener.local_addr().unwrap()
+        );
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let if_none_match = String::from_utf8_lossy(&request)
+                    .lines()
+                    .find_map(|line| line.strip_prefix("if-none-match: "))
+                    .map(str::to_string);
+                let response = if if_none_match.as_deref() == Some("\"v1\"") {
+                    "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n"
+                        .to_string()
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+                recorded.lock().unwrap().push(if_none_match);
+                let _ = stream.write_all(response.as_bytes());
+                let _ = stream.flush();
+            }
+        });
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_updates_cache() -> Result<(), BoxError> {
+        let tmp = TempDir::new("manifest-fetch");
+        let (url, requests) = serve(fixture());
+        let fetched = fetch_manifest(&url, tmp.path()).await?;
+        assert!(tmp.path().join(MANIFEST_CACHE_FILE).is_file());
+        let meta = read_meta(tmp.path()).unwrap();
+        assert_eq!(meta.etag.as_deref(), Some("\"v1\""));
+
+        // the second fetch is answered with 304 Not Modified and served from the cache
+        let cached = fetch_manifest(&url, tmp.path()).await?;
+        assert!(cached.diff(&fetched).is_empty());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [None, Some("\"v1\"".to_string())]
+        );
+        Ok(())
+    }
+}
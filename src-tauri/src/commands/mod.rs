@@ -1,7 +1,34 @@
+/// The path `$path` below the BepInEx folder, relative to the game folder, or the folder itself.
+/// It is spelled as in the BepInEx zips everywhere, since the file system may be case-sensitive.
+macro_rules! bepinex_path {
+    () => {
+        "BepInEx"
+    };
+    ($path:literal) => {
+        concat!(bepinex_path!(), "/", $path)
+    };
+}
+
+mod assembly;
+mod bepinex;
+pub mod bepinex_config;
+pub mod cancel;
 mod checksum;
+pub mod clone_strategy;
 pub mod download;
+mod download_cache;
+mod extract;
 pub mod file_utils;
 pub mod game;
+mod hash_cache;
+mod http;
+mod install_manifest;
+mod lethe;
+mod manifest_parser;
+mod manifest_source;
 pub mod patch;
+pub mod plugins;
+mod progress;
+mod release;
 pub mod sandboxie;
 pub mod steam;
@@ -0,0 +1,373 @@
+use crate::commands::assembly;
+use crate::commands::checksum::{remove_file_if_exists, sibling, BoxError};
+use crate::commands::install_manifest::remove_empty_parents;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Relative to the game folder.
+pub const PLUGINS_DIR: &str = bepinex_path!("plugins");
+/// Where disabled plugins are moved, relative to the game folder. BepInEx loads plugins from
+/// every folder below `plugins`, so this cannot be one of them.
+pub const DISABLED_DIR: &str = bepinex_path!("disabled");
+
+/// A plugin DLL in the plugins or disabled folder.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginInfo {
+    /// Path relative to the plugins folder, or the disabled folder while disabled.
+    pub file: String,
+    pub enabled: bool,
+    /// Read from the `[BepInPlugin]` attribute, if the DLL has one.
+    pub guid: Option<String>,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    /// Another enabled plugin has the same GUID, so BepInEx loads only one of them.
+    pub duplicate: bool,
+}
+
+impl PluginInfo {
+    fn read(root: &Path, file: String, enabled: bool) -> PluginInfo {
+        let path = root.join(&file);
+        let attribute = fs::read(&path)
+            .map_err(BoxError::from)
+            .and_then(|data| assembly::read_plugin_attributes(&data))
+            .unwrap_or_else(|e| {
+                log::debug!("No plugin metadata in {}: {}", path.display(), e);
+                Vec::new()
+            })
+            .into_iter()
+            .next();
+        let (guid, name, version) = match attribute {
+            Some(attribute) => (Some(attribute.guid), attribute.name, attribute.version),
+            None => (None, None, None),
+        };
+        PluginInfo {
+            file,
+            enabled,
+            guid,
+            name,
+            version,
+            duplicate: false,
+        }
+    }
+}
+
+/// A plugin was refused because BepInEx would load only one of it and an enabled plugin.
+#[derive(Debug, PartialEq)]
+pub struct DuplicatePlugin {
+    pub guid: String,
+    /// The enabled plugin with the same GUID.
+    pub file: String,
+}
+
+impl Display for DuplicatePlugin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "A plugin with the GUID {} is already enabled: {}",
+            self.guid, self.file
+        )
+    }
+}
+
+impl Error for DuplicatePlugin {}
+
+/// The DLLs below `dir`, relative to it and with `/` as separator.
+fn find_dlls(dir: &Path, prefix: &str, found: &mut Vec<String>) -> Result<(), BoxError> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            find_dlls(&entry.path(), &format!("{}/", name), found)?;
+        } else if name.to_ascii_lowercase().ends_with(".dll") {
+            found.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// Every plugin in `game_dir`, enabled ones first.
+pub fn list(game_dir: &Path) -> Result<Vec<PluginInfo>, BoxError> {
+    let mut plugins = Vec::new();
+    for (dir, enabled) in [(PLUGINS_DIR, true), (DISABLED_DIR, false)] {
+        let root = game_dir.join(dir);
+        let mut files = Vec::new();
+        find_dlls(&root, "", &mut files)?;
+        files.sort();
+        plugins.extend(
+            files
+                .into_iter()
+                .map(|file| PluginInfo::read(&root, file, enabled)),
+        );
+    }
+
+    let mut enabled_guids: HashMap<String, usize> = HashMap::new();
+    for guid in plugins
+        .iter()
+        .filter(|p| p.enabled)
+        .filter_map(|p| p.guid.clone())
+    {
+        *enabled_guids.entry(guid).or_default() += 1;
+    }
+    for plugin in plugins.iter_mut().filter(|p| p.enabled) {
+        plugin.duplicate = plugin
+            .guid
+            .as_ref()
+            .is_some_and(|guid| enabled_guids[guid] > 1);
+    }
+    Ok(plugins)
+}
+
+/// Resolves `file` below `root`, refusing paths that leave it.
+fn plugin_path(root: &Path, file: &str) -> Result<PathBuf, BoxError> {
+    let relative = Path::new(file);
+    let normal = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if file.is_empty() || !normal {
+        return Err(format!("Invalid plugin path: {}", file).into());
+    }
+    Ok(root.join(relative))
+}
+
+/// Fails if an enabled plugin other than `file` has `guid`.
+fn check_duplicate(game_dir: &Path, guid: Option<&str>, file: &str) -> Result<(), BoxError> {
+    let Some(guid) = guid else {
+        return Ok(());
+    };
+    let existing = list(game_dir)?
+        .into_iter()
+        .find(|p| p.enabled && p.file != file && p.guid.as_deref() == Some(guid));
+    match existing {
+        Some(existing) => Err(DuplicatePlugin {
+            guid: guid.to_string(),
+            file: existing.file,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Enables or disables the plugin `file` by moving it between the plugins and disabled folders.
+/// A plugin is not enabled while another one with the same GUID is.
+pub fn set_enabled(game_dir: &Path, file: &str, enabled: bool) -> Result<PluginInfo, BoxError> {
+    let (from_dir, to_dir) = if enabled {
+        (DISABLED_DIR, PLUGINS_DIR)
+    } else {
+        (PLUGINS_DIR, DISABLED_DIR)
+    };
+    let (from_root, to_root) = (game_dir.join(from_dir), game_dir.join(to_dir));
+    let to = plugin_path(&to_root, file)?;
+    let from = plugin_path(&from_root, file)?;
+    if !from.is_file() {
+        if to.is_file() {
+            return Ok(PluginInfo::read(&to_root, file.to_string(), enabled));
+        }
+        return Err(format!("No plugin {}", file).into());
+    }
+    if to.exists() {
+        return Err(format!("{} exists both enabled and disabled", file).into());
+    }
+    if enabled {
+        let plugin = PluginInfo::read(&from_root, file.to_string(), false);
+        check_duplicate(game_dir, plugin.guid.as_deref(), file)?;
+    }
+
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(&from, &to)?;
+    remove_empty_parents(&from_root, &from);
+    Ok(PluginInfo::read(&to_root, file.to_string(), enabled))
+}
+
+/// Fails unless `file_name` is a plain file name a plugin DLL can be installed as.
+pub fn check_file_name(file_name: &str) -> Result<(), BoxError> {
+    if !file_name.to_ascii_lowercase().ends_with(".dll") || file_name.contains(['/', '\\']) {
+        return Err(format!("{} is not a plugin DLL", file_name).into());
+    }
+    Ok(())
+}
+
+/// Copies the plugin DLL `source` into the plugins folder as `file_name`, replacing an older
+/// copy of it, enabled or disabled. It is refused if another enabled plugin has the same GUID, or
+/// if a disabled plugin with a different GUID has the same file name.
+pub fn install(game_dir: &Path, source: &Path, file_name: &str) -> Result<PluginInfo, BoxError> {
+    check_file_name(file_name)?;
+    let attributes = assembly::read_plugin_attributes(&fs::read(source)?)
+        .map_err(|e| format!("{} is not a .NET plugin: {}", file_name, e))?;
+    let guid = attributes.first().map(|attribute| attribute.guid.as_str());
+    check_duplicate(game_dir, guid, file_name)?;
+    // a disabled copy would clash with the new one once enabled, so it has to be the same plugin
+    let disabled_root = game_dir.join(DISABLED_DIR);
+    let disabled = plugin_path(&disabled_root, file_name)?;
+    if disabled.exists() {
+        let other = PluginInfo::read(&disabled_root, file_name.to_string(), false);
+        if guid.is_none() || other.guid.as_deref() != guid {
+            return Err(format!(
+                "A different plugin named {} is disabled, remove it before installing this one",
+                file_name
+            )
+            .into());
+        }
+    }
+
+    let root = game_dir.join(PLUGINS_DIR);
+    let destination = plugin_path(&root, file_name)?;
+    fs::create_dir_all(&root)?;
+    let staging = sibling(&destination, "new");
+    fs::copy(source, &staging)?;
+    if let Err(err) = fs::rename(&staging, &destination) {
+        remove_file_if_exists(&staging)?;
+        return Err(err.into());
+    }
+    remove_file_if_exists(&disabled)?;
+    log::info!("Installed plugin {}", file_name);
+    Ok(PluginInfo::read(&root, file_name.to_string(), true))
+}
+
+/// Lists the BepInEx plugins of `./game`, with what their DLLs say about them.
+#[tauri::command]
+pub async fn list_plugins() -> Result<Vec<PluginInfo>, String> {
+    list(Path::new("./game")).map_err(|e| format!("Failed to list plugins: {}", e))
+}
+
+/// Enables or disables the plugin `file`, as returned by [`list_plugins`].
+#[tauri::command]
+pub async fn set_plugin_enabled(file: String, enabled: bool) -> Result<PluginInfo, String> {
+    set_enabled(Path::new("./game"), &file, enabled).map_err(|e| e.to_string())
+}
+
+/// Installs the plugin DLL at `path` into `./game`.
+#[tauri::command]
+pub async fn install_plugin_from_file(path: String) -> Result<PluginInfo, String> {
+    let source = Path::new(&path);
+    let file_name = source
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| format!("Invalid plugin path: {}", path))?;
+    install(Path::new("./game"), source, &file_name)
+        .map_err(|e| format!("Failed to install {}: {}", file_name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::assembly::tests::plugin_dll;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn test_enable_and_disable() {
+        let temp = TempDir::new("plugins");
+        let game = temp.path().join("game");
+        let plugins = game.join(PLUGINS_DIR);
+        fs::create_dir_all(plugins.join("Tools")).unwrap();
+        fs::write(
+            plugins.join("Lethe.dll"),
+            plugin_dll("carra.lethe", "Lethe", "1.2.0"),
+        )
+        .unwrap();
+        fs::write(
+            plugins.join("Tools/Tools.dll"),
+            plugin_dll("team.tools", "Tools", "0.3.1"),
+        )
+        .unwrap();
+        fs::write(plugins.join("Tools/Native.dll"), b"not .NET").unwrap();
+
+        let listed = list(&game).unwrap();
+        let files: Vec<_> = listed.iter().map(|p| p.file.as_str()).collect();
+        assert_eq!(files, ["Lethe.dll", "Tools/Native.dll", "Tools/Tools.dll"]);
+        assert_eq!(listed[0].guid.as_deref(), Some("carra.lethe"));
+        assert_eq!(listed[0].version.as_deref(), Some("1.2.0"));
+        assert_eq!(listed[1].guid, None);
+
+        let disabled = set_enabled(&game, "Tools/Tools.dll", false).unwrap();
+        assert!(!disabled.enabled);
+        assert_eq!(disabled.name.as_deref(), Some("Tools"));
+        assert!(game.join(DISABLED_DIR).join("Tools/Tools.dll").is_file());
+        assert!(plugins.join("Tools/Native.dll").is_file());
+
+        set_enabled(&game, "Tools/Native.dll", false).unwrap();
+        assert!(!plugins.join("Tools").exists());
+        assert!(plugins.is_dir());
+
+        assert!(set_enabled(&game, "../Lethe.dll", false).is_err());
+        assert!(set_enabled(&game, "Missing.dll", true).is_err());
+        assert!(set_enabled(&game, "Tools/Tools.dll", true).unwrap().enabled);
+        assert!(plugins.join("Tools/Tools.dll").is_file());
+    }
+
+    #[test]
+    fn test_duplicate_guids() {
+        let temp = TempDir::new("plugins-duplicate");
+        let game = temp.path().join("game");
+        let plugins = game.join(PLUGINS_DIR);
+        fs::create_dir_all(&plugins).unwrap();
+        let lethe = plugin_dll("carra.lethe", "Lethe", "1.2.0");
+        fs::write(plugins.join("Lethe.dll"), &lethe).unwrap();
+        fs::write(plugins.join("Lethe-old.dll"), &lethe).unwrap();
+
+        let listed = list(&game).unwrap();
+        assert!(listed.iter().all(|p| p.duplicate));
+
+        set_enabled(&game, "Lethe-old.dll", false).unwrap();
+        assert!(list(&game).unwrap().iter().all(|p| !p.duplicate));
+        let err = set_enabled(&game, "Lethe-old.dll", true).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DuplicatePlugin>(),
+            Some(&DuplicatePlugin {
+                guid: "carra.lethe".to_string(),
+                file: "Lethe.dll".to_string(),
+            })
+        );
+
+        let source = temp.path().join("Lethe-copy.dll");
+        fs::write(&source, &lethe).unwrap();
+        let err = install(&game, &source, "Lethe-copy.dll").unwrap_err();
+        assert!(err.downcast_ref::<DuplicatePlugin>().is_some());
+
+        // installing under the same name updates the plugin
+        let update = plugin_dll("carra.lethe", "Lethe", "1.3.0");
+        fs::write(&source, update).unwrap();
+        let installed = install(&game, &source, "Lethe.dll").unwrap();
+        assert_eq!(installed.version.as_deref(), Some("1.3.0"));
+
+        fs::write(&source, b"not .NET").unwrap();
+        assert!(install(&game, &source, "Native.dll").is_err());
+        assert!(!plugins.join("Native.dll").exists());
+
+        for name in ["BepInEx.cfg", "../Lethe.dll", "sub\\Lethe.dll"] {
+            assert!(check_file_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_install_over_disabled_plugin() {
+        let temp = TempDir::new("plugins-install-disabled");
+        let game = temp.path().join("game");
+        let disabled = game.join(DISABLED_DIR);
+        fs::create_dir_all(&disabled).unwrap();
+        let tools = plugin_dll("team.tools", "Tools", "0.3.1");
+        fs::write(disabled.join("Tools.dll"), &tools).unwrap();
+
+        // another plugin with the same file name leaves the disabled one alone
+        let source = temp.path().join("Tools.dll");
+        fs::write(&source, plugin_dll("other.tools", "Other Tools", "1.0.0")).unwrap();
+        assert!(install(&game, &source, "Tools.dll").is_err());
+        assert_eq!(fs::read(disabled.join("Tools.dll")).unwrap(), tools);
+        assert!(!game.join(PLUGINS_DIR).join("Tools.dll").exists());
+
+        // a new version of the same plugin replaces it
+        fs::write(&source, plugin_dll("team.tools", "Tools", "0.4.0")).unwrap();
+        let installed = install(&game, &source, "Tools.dll").unwrap();
+        assert_eq!(installed.version.as_deref(), Some("0.4.0"));
+        assert!(!disabled.join("Tools.dll").exists());
+    }
+}
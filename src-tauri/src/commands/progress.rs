@@ -0,0 +1,208 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+/// Name of the event progress updates are emitted as.
+pub const PROGRESS_EVENT: &str = "operation-progress";
+
+/// Minimum time between two progress events of the same operation.
+const EMIT_INTERVAL: Duration = Duration::from_millis(150);
+
+/// Snapshot of a long running operation, as sent to the UI.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressEvent {
+    /// Id to pass to `cancel_operation` to stop the operation.
+    pub operation_id: String,
+    pub operation: String,
+    pub phase: String,
+    pub current_file: Option<String>,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub files_done: u64,
+    pub files_total: u64,
+    /// Estimated seconds left in the current phase, once there is enough data to guess.
+    pub eta_secs: Option<u64>,
+    pub finished: bool,
+}
+
+struct ProgressState {
+    event: ProgressEvent,
+    phase_started: Instant,
+    last_emit: Option<Instant>,
+}
+
+type ProgressSink = Box<dyn Fn(&ProgressEvent) + Send + Sync>;
+
+/// Tracks the progress of one operation and reports it, throttled, to a sink. Can be shared
+/// between worker threads.
+pub struct ProgressReporter {
+    sink: ProgressSink,
+    state: Mutex<ProgressState>,
+}
+
+impl ProgressReporter {
+    /// Reports progress of `operation` as Tauri events.
+    pub fn new(app: &AppHandle, operation: &str, operation_id: &str) -> ProgressReporter {
+        let app = app.clone();
+        ProgressReporter::with_sink(operation, operation_id, move |event| {
+            if let Err(err) = app.emit(PROGRESS_EVENT, event) {
+                log::error!("Failed to emit progress: {}", err);
+            }
+        })
+    }
+
+    pub fn with_sink<F>(operation: &str, operation_id: &str, sink: F) -> ProgressReporter
+    where
+        F: Fn(&ProgressEvent) + Send + Sync + 'static,
+    {
+        ProgressReporter {
+            sink: Box::new(sink),
+            state: Mutex::new(ProgressState {
+                event: ProgressEvent {
+                    operation_id: operation_id.to_string(),
+                    operation: operation.to_string(),
+                    ..Default::default()
+                },
+                phase_started: Instant::now(),
+                last_emit: None,
+            }),
+        }
+    }
+
+    fn update<F>(&self, force: bool, change: F)
+    where
+        F: FnOnce(&mut ProgressEvent),
+    {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state.event);
+
+        let now = Instant::now();
+        let due = state
+            .last_emit
+            .is_none_or(|last| now.duration_since(last) >= EMIT_INTERVAL);
+        if !force && !due {
+            return;
+        }
+
+        state.last_emit = Some(now);
+        let elapsed = now.duration_since(state.phase_started).as_secs_f64();
+        let event = &mut state.event;
+        event.eta_secs = estimate_eta(event, elapsed);
+        (self.sink)(event);
+    }
+
+    /// Starts a new phase of the operation, resetting the counters.
+    pub fn start_phase(&self, phase: &str, files_total: u64, bytes_total: u64) {
+        self.state.lock().unwrap().phase_started = Instant::now();
+        self.update(true, |event| {
+            event.phase = phase.to_string();
+            event.current_file = None;
+            event.bytes_done = 0;
+            event.bytes_total = bytes_total;
+            event.files_done = 0;
+            event.files_total = files_total;
+        });
+    }
+
+    /// Updates the total once it is known, e.g. from the response of a download.
+    pub fn set_bytes_total(&self, bytes_total: u64) {
+        self.update(false, |event| event.bytes_total = bytes_total);
+    }
+
+    pub fn file_started(&self, name: &str) {
+        self.update(false, |event| event.current_file = Some(name.to_string()));
+    }
+
+    pub fn file_done(&self) {
+        self.update(false, |event| event.files_done += 1);
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.update(false, |event| event.bytes_done += bytes);
+    }
+
+    /// Marks the operation as done. Always reported, regardless of throttling.
+    pub fn finish(&self) {
+        self.update(true, |event| {
+            event.current_file = None;
+            event.finished = true;
+        });
+    }
+}
+
+fn estimate_eta(event: &ProgressEvent, elapsed: f64) -> Option<u64> {
+    let (done, total) = if event.bytes_total > 0 {
+        (event.bytes_done, event.bytes_total)
+    } else {
+        (event.files_done, event.files_total)
+    };
+    if done == 0 || total <= done || elapsed < 1.0 {
+        return None;
+    }
+
+    let rate = done as f64 / elapsed;
+    Some(((total - done) as f64 / rate).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn recording_reporter() -> (ProgressReporter, Arc<Mutex<Vec<ProgressEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let reporter = ProgressReporter::with_sink("clone", "op-1", move |event| {
+            sink.lock().unwrap().push(event.clone())
+        });
+        (reporter, events)
+    }
+
+    #[test]
+    fn test_progress_is_throttled() {
+        let (reporter, events) = recording_reporter();
+        reporter.start_phase("copy", 1000, 1000);
+        for i in 0..1000 {
+            reporter.file_started(&format!("file_{}", i));
+            reporter.add_bytes(1);
+            reporter.file_done();
+        }
+        reporter.finish();
+
+        let events = events.lock().unwrap();
+        assert!(events.len() < 10, "{} events were emitted", events.len());
+
+        let first = events.first().unwrap();
+        assert_eq!((first.phase.as_str(), first.files_done), ("copy", 0));
+
+        let last = events.last().unwrap();
+        assert!(last.finished);
+        assert_eq!(
+            (last.operation.as_str(), last.operation_id.as_str()),
+            ("clone", "op-1")
+        );
+        assert_eq!((last.files_done, last.files_total), (1000, 1000));
+        assert_eq!((last.bytes_done, last.bytes_total), (1000, 1000));
+    }
+
+    #[test]
+    fn test_eta_estimate() {
+        let event = ProgressEvent {
+            bytes_done: 250,
+            bytes_total: 1000,
+            ..Default::default()
+        };
+        assert_eq!(estimate_eta(&event, 10.0), Some(30));
+        assert_eq!(estimate_eta(&event, 0.5), None);
+
+        let by_files = ProgressEvent {
+            files_done: 10,
+            files_total: 20,
+            ..Default::default()
+        };
+        assert_eq!(estimate_eta(&by_files, 4.0), Some(4));
+        assert_eq!(estimate_eta(&ProgressEvent::default(), 4.0), None);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::commands::checksum::{calculate_checksum, BoxError};
+use crate::commands::http::{self, HttpError};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+/// The BepInEx build Lethe is made for, installed unless another version is asked for.
+pub const DEFAULT_BEPINEX_VERSION: &str = "6.0.0-be.733";
+const BEPINEX_733_URL: &str = "https://builds.bepinex.dev/projects/bepinex_be/733/BepInEx-Unity.IL2CPP-win-x64-6.0.0-be.733%2B995f049.zip";
+/// SHA-1 of the zip at [`BEPINEX_733_URL`], in hex. Builds never change once published, so the
+/// hash is pinned here instead of being fetched. It still has to be taken from a verified
+/// download; a release without a pinned hash is not offered, so be.733 cannot be installed until
+/// then.
+const BEPINEX_733_SHA1: Option<&str> = None;
+
+/// Runtime and architecture a BepInEx build is made for. Limbus Company needs `Il2cppX64`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BepInExFlavor {
+    #[default]
+    Il2cppX64,
+    Il2cppX86,
+    MonoX64,
+    MonoX86,
+}
+
+impl BepInExFlavor {
+    /// The name BepInEx builds use for the flavor, as in `il2cpp-x64`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BepInExFlavor::Il2cppX64 => "il2cpp-x64",
+            BepInExFlavor::Il2cppX86 => "il2cpp-x86",
+            BepInExFlavor::MonoX64 => "mono-x64",
+            BepInExFlavor::MonoX86 => "mono-x86",
+        }
+    }
+}
+
+/// A BepInEx build that can be installed into the game folder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BepInExRelease {
+    pub version: String,
+    pub flavor: BepInExFlavor,
+    pub url: String,
+    /// SHA-1 of the zip, in hex.
+    pub sha1: String,
+}
+
+impl BepInExRelease {
+    /// Name the zip is saved under.
+    pub fn file_name(&self) -> String {
+        format!("BepInEx-{}-{}.zip", self.flavor.as_str(), self.version)
+    }
+}
+
+/// The releases known without asking the server, leaving out those without a pinned hash.
+pub fn builtin_bepinex_releases() -> Vec<BepInExRelease> {
+    BEPINEX_733_SHA1
+        .map(|sha1| BepInExRelease {
+            version: DEFAULT_BEPINEX_VERSION.to_string(),
+            flavor: BepInExFlavor::Il2cppX64,
+            url: BEPINEX_733_URL.to_string(),
+            sha1: sha1.to_string(),
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Fetches the releases listed in the BepInEx index at `index_url`, a JSON array of
+/// [`BepInExRelease`]. There is no official index, so its location is up to the caller. Its
+/// hashes are not signed and only catch corrupted downloads.
+pub async fn fetch_bepinex_releases(index_url: &str) -> Result<Vec<BepInExRelease>, BoxError> {
+    let response = http::send(index_url, |client| client.get(index_url)).await?;
+    let text = response
+        .text()
+        .await
+        .map_err(|err| HttpError::from_reqwest(index_url, err))?;
+    let releases = serde_json::from_str(&text)
+        .map_err(|err| format!("Invalid BepInEx release index: {}", err))?;
+    Ok(releases)
+}
+
+/// The built-in releases followed by those of the index at `index_url`, if given. Remote
+/// entries cannot replace built-in ones, so the pinned hashes always win.
+pub async fn bepinex_releases(index_url: Option<&str>) -> Vec<BepInExRelease> {
+    let mut releases = builtin_bepinex_releases();
+    if let Some(index_url) = index_url {
+        match fetch_bepinex_releases(index_url).await {
+            Ok(fetched) => {
+                for release in fetched {
+                    let known = releases.iter().any(|known| {
+                        known.version == release.version && known.flavor == release.flavor
+                    });
+                    if !known {
+                        releases.push(release);
+                    }
+                }
+            }
+            Err(err) => log::warn!("Failed to fetch the BepInEx release index: {}", err),
+        }
+    }
+    releases
+}
+
+/// Finds the release `version` of `flavor`, asking the index at `index_url` only if it is not
+/// built in.
+pub async fn find_bepinex_release(
+    version: &str,
+    flavor: BepInExFlavor,
+    index_url: Option<&str>,
+) -> Result<BepInExRelease, BoxError> {
+    let matches = |release: &BepInExRelease| release.version == version && release.flavor == flavor;
+    if let Some(release) = builtin_bepinex_releases().into_iter().find(matches) {
+        return Ok(release);
+    }
+    let remote = match index_url {
+        Some(index_url) => fetch_bepinex_releases(index_url).await?,
+        None => Vec::new(),
+    };
+    remote.into_iter().find(matches).ok_or_else(|| {
+        format!(
+            "BepInEx {} ({:?}) is not a known release with a pinned hash",
+            version, flavor
+        )
+        .into()
+    })
+}
+
+/// Describes the current Lethe release, including the hash its download has to match. The index
+/// is not signed and comes from the same host as the DLL, so the hash catches corrupted or
+/// truncated downloads, not a compromised server.
+pub const LETHE_INDEX_URL: &str = "https://api.lethelc.site/lethe-release.json";
+
+/// Keeps the Lethe release the index listed last, relative to the app data folder.
+pub const LAST_LETHE_RELEASE_FILE: &str = "lethe-release.json";
+
+/// A Lethe release as listed in the release index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LetheRelease {
+    pub version: String,
+    pub url: String,
+    /// SHA-1 of the DLL, in hex.
+    pub sha1: String,
+}
+
+impl LetheRelease {
+    /// The release saved to `path`, if there is a readable one.
+    pub fn load(path: &Path) -> Option<LetheRelease> {
+        let text = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), BoxError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Fetches the current Lethe release from the release index.
+pub async fn fetch_lethe_release() -> Result<LetheRelease, BoxError> {
+    let response = http::send(LETHE_INDEX_URL, |client| client.get(LETHE_INDEX_URL)).await?;
+    let text = response
+        .text()
+        .await
+        .map_err(|err| HttpError::from_reqwest(LETHE_INDEX_URL, err))?;
+    let release: LetheRelease = serde_json::from_str(&text)
+        .map_err(|err| format!("Invalid Lethe release index: {}", err))?;
+    Ok(release)
+}
+
+/// A downloaded file did not have the hash it was supposed to have.
+#[derive(Debug, PartialEq)]
+pub struct IntegrityError {
+    pub name: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for IntegrityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} failed the integrity check (expected SHA-1 {}, got {}). It may be corrupted or \
+             tampered with and was not installed.",
+            self.name, self.expected, self.actual
+        )
+    }
+}
+
+impl Error for IntegrityError {}
+
+/// Checks that the file at `path` has the SHA-1 `expected`. `name` is used in the error. A file
+/// is never accepted without a proper hash to check it against.
+pub fn verify_file(path: &Path, name: &str, expected: &str) -> Result<(), BoxError> {
+    if expected.len() != 40 || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{} has no valid SHA-1 to be checked against", name).into());
+    }
+    let actual = calculate_checksum(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(IntegrityError {
+            name: name.to_string(),
+            expected: expected.to_uppercase(),
+            actual,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn test_verify_file() {
+        let tmp = TempDir::new("release-verify");
+        let path = tmp.path().join("Lethe.dll");
+        std::fs::write(&path, b"hello world").unwrap();
+
+        let sha1 = "2AAE6C35C94FCFB415DBE95F408B9CE91EE846ED";
+        verify_file(&path, "Lethe.dll", sha1).unwrap();
+        verify_file(&path, "Lethe.dll", &sha1.to_lowercase()).unwrap();
+
+        let wrong = "0123456789ABCDEF0123456789ABCDEF01234567";
+        let err = verify_file(&path, "Lethe.dll", wrong).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(&IntegrityError {
+                name: "Lethe.dll".to_string(),
+                expected: wrong.to_string(),
+                actual: sha1.to_string(),
+            })
+        );
+
+        for missing in ["", "2AAE6C35", "not a hash, but forty characters long...."] {
+            let err = verify_file(&path, "Lethe.dll", missing).unwrap_err();
+            assert!(err.downcast_ref::<IntegrityError>().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unpinned_release_is_not_offered() {
+        // without an index only built-in releases with a pinned hash can be installed
+        let err = find_bepinex_release("6.0.0-be.735", BepInExFlavor::Il2cppX64, None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("pinned hash"), "{}", err);
+        for release in builtin_bepinex_releases() {
+            assert_eq!(release.sha1.len(), 40, "{}", release.version);
+        }
+    }
+
+    #[test]
+    fn test_parse_lethe_release() {
+        let release: LetheRelease = serde_json::from_str(
+            r#"{"version": "1.4.2", "url": "https://api.lethelc.site/Lethe.dll", "sha1": "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed"}"#,
+        )
+        .unwrap();
+        assert_eq!(release.version, "1.4.2");
+        assert_eq!(release.url, "https://api.lethelc.site/Lethe.dll");
+    }
+
+    #[test]
+    fn test_save_lethe_release() {
+        let tmp = TempDir::new("release-lethe-save");
+        let path = tmp.path().join("data").join(LAST_LETHE_RELEASE_FILE);
+        assert_eq!(LetheRelease::load(&path), None);
+
+        let release = LetheRelease {
+            version: "1.2.3".to_string(),
+            url: "https://example.com/Lethe.dll".to_string(),
+            sha1: "2AAE6C35C94FCFB415DBE95F408B9CE91EE846ED".to_string(),
+        };
+        release.save(&path).unwrap();
+        assert_eq!(LetheRelease::load(&path), Some(release));
+    }
+
+    #[test]
+    fn test_parse_bepinex_index() {
+        let releases: Vec<BepInExRelease> = serde_json::from_str(
+            r#"[{"version": "6.0.0-be.735", "flavor": "il2cpp-x64", "url": "https://builds.bepinex.dev/735.zip", "sha1": "2AAE6C35C94FCFB415DBE95F408B9CE91EE846ED"}]"#,
+        )
+        .unwrap();
+        assert_eq!(releases[0].flavor, BepInExFlavor::Il2cppX64);
+        assert_eq!(
+            releases[0].file_name(),
+            "BepInEx-il2cpp-x64-6.0.0-be.735.zip"
+        );
+        for flavor in [
+            BepInExFlavor::Il2cppX64,
+            BepInExFlavor::Il2cppX86,
+            BepInExFlavor::MonoX64,
+            BepInExFlavor::MonoX86,
+        ] {
+            assert_eq!(serde_json::json!(flavor), flavor.as_str());
+        }
+    }
+}
@@ -1,6 +1,18 @@
-use commands::download::{download_and_extract_bepinex, download_and_install_lethe};
-use commands::file_utils::{check_lethe_limbus_up_to_date, clone_folder_to_game, open_game_folder};
+use commands::bepinex_config::{get_bepinex_config, set_bepinex_config_value};
+use commands::cancel::cancel_operation;
+use commands::download::{
+    check_lethe_update, download_and_extract_bepinex, download_and_install_lethe,
+    get_installed_bepinex, install_bepinex_from_file, install_lethe_from_file,
+    install_plugin_from_url, list_bepinex_releases, prune_download_cache, rollback_lethe,
+    uninstall_component,
+};
+use commands::file_utils::{
+    check_lethe_limbus_up_to_date, clone_folder_to_game, delete_orphaned_game_files,
+    diff_limbus_manifests, find_orphaned_game_files, generate_limbus_manifest, open_game_folder,
+    sync_folder_to_game, verify_limbus_folder,
+};
 use commands::patch::patch_limbus;
+use commands::plugins::{install_plugin_from_file, list_plugins, set_plugin_enabled};
 use commands::sandboxie::{
     sandboxie_block_cache_folders, sandboxie_block_user_registry, sandboxie_permit_plugins_folder,
     sandboxie_revoke_plugins_folder, sandboxie_unblock_cache_folders,
@@ -13,6 +25,8 @@ use tauri_plugin_store::StoreExt;
 use tokio::task;
 use utils::extract_value;
 mod commands;
+#[cfg(test)]
+mod test_utils;
 mod utf16le_utils;
 mod utils;
 
@@ -56,10 +70,31 @@ pub fn run() {
             steam_limbus_location,
             download_and_extract_bepinex,
             download_and_install_lethe,
+            list_bepinex_releases,
+            get_installed_bepinex,
+            check_lethe_update,
+            rollback_lethe,
+            prune_download_cache,
+            uninstall_component,
+            install_bepinex_from_file,
+            install_lethe_from_file,
+            get_bepinex_config,
+            set_bepinex_config_value,
+            list_plugins,
+            set_plugin_enabled,
+            install_plugin_from_file,
+            install_plugin_from_url,
             patch_limbus,
             open_game_folder,
             clone_folder_to_game,
+            sync_folder_to_game,
             check_lethe_limbus_up_to_date,
+            verify_limbus_folder,
+            find_orphaned_game_files,
+            delete_orphaned_game_files,
+            diff_limbus_manifests,
+            generate_limbus_manifest,
+            cancel_operation,
             sandboxie_permit_plugins_folder,
             sandboxie_block_cache_folders,
             sandboxie_revoke_plugins_folder,
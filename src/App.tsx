@@ -10,19 +10,30 @@ import { useErrorContext } from "./context/ErrorContext";
 import { useEffect } from "react";
 import { useToast } from "./context/ToastContext";
 
+type InstallCheck = {
+  status: "upToDate" | "outdated" | "corrupted" | "notInstalled";
+  reasons: { path: string; message: string }[];
+};
+
 function App() {
   const { error, setError } = useErrorContext();
   const { showToast } = useToast();
 
   async function checkUpdate() {
     try {
-      const upToDate = await invoke<boolean>("check_lethe_limbus_up_to_date");
-      if (!upToDate) {
+      const check = await invoke<InstallCheck>("check_lethe_limbus_up_to_date");
+      if (check.status === "outdated") {
         showToast(
           "A new limbus version has been detected. Consider updating.",
           "alert-info",
           10000
         );
+      } else if (check.status === "corrupted") {
+        showToast(
+          "Some limbus files are missing or damaged. Consider updating.",
+          "alert-warn",
+          10000
+        );
       }
     } catch (err) {
       console.error(err);